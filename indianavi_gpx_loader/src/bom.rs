@@ -0,0 +1,165 @@
+use unicode_bom::Bom;
+
+use crate::LoadError;
+
+/// The content of a text track file as UTF-8 without byte order mark.
+///
+/// Files starting with a UTF-16 or UTF-32 byte order mark are transcoded,
+/// a UTF-8 mark is dropped and files without one are returned as they are.
+/// The encoding an XML declaration names is changed to UTF-8 as well, XML
+/// parsers reject files that use another one than they declare.
+///
+/// # Errors
+///
+/// Fails for other byte order marks and for content that is not valid in the
+/// encoding its mark announces.
+pub fn decode(mut data: Vec<u8>) -> Result<Vec<u8>, LoadError> {
+    let bom = Bom::from(data.as_slice());
+    let text = &data[bom.len()..];
+    let text = match bom {
+        Bom::Null => return Ok(data),
+        Bom::Utf8 => {
+            data.drain(..bom.len());
+            return Ok(data);
+        }
+        Bom::Utf16Le => utf16(text, u16::from_le_bytes),
+        Bom::Utf16Be => utf16(text, u16::from_be_bytes),
+        Bom::Utf32Le => utf32(text, u32::from_le_bytes),
+        Bom::Utf32Be => utf32(text, u32::from_be_bytes),
+        other => Err(format!("{other} encoded files are not supported")),
+    }
+    .map_err(LoadError::Encoding)?;
+    Ok(declare_utf8(text).into_bytes())
+}
+
+/// Replaces the encoding of the `<?xml … ?>` declaration at the start of
+/// `text`.
+fn declare_utf8(mut text: String) -> String {
+    let declaration = text
+        .starts_with("<?xml")
+        .then(|| text.find("?>"))
+        .flatten()
+        .unwrap_or(0);
+    let Some(start) = text[..declaration].find("encoding") else {
+        return text;
+    };
+    let value = text[start..declaration]
+        .find(['"', '\''])
+        .map(|open| start + open)
+        .and_then(|open| {
+            let quote = text[open..].chars().next()?;
+            let close = text[open + 1..declaration].find(quote)?;
+            Some(open + 1..open + 1 + close)
+        });
+    if let Some(value) = value {
+        text.replace_range(value, "UTF-8");
+    }
+    text
+}
+
+fn utf16(data: &[u8], unit: fn([u8; 2]) -> u16) -> Result<String, String> {
+    let units = data.chunks(2).map(|c| {
+        c.try_into()
+            .map(unit)
+            .map_err(|_| "UTF-16: odd number of bytes".to_string())
+    });
+    let units: Vec<u16> = units.collect::<Result<_, _>>()?;
+    char::decode_utf16(units)
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("UTF-16: {e}"))
+}
+
+fn utf32(data: &[u8], unit: fn([u8; 4]) -> u32) -> Result<String, String> {
+    data.chunks(4)
+        .map(|c| {
+            let value = c
+                .try_into()
+                .map(unit)
+                .map_err(|_| "UTF-32: length is not a multiple of 4 bytes".to_string())?;
+            char::from_u32(value).ok_or_else(|| format!("UTF-32: invalid character {value:#x}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load, TrackFormat};
+
+    const TRACK: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test">
+  <trk><name>Höhenweg ⛰</name><trkseg>
+    <trkpt lat="47.1" lon="8.1"><ele>1200</ele></trkpt>
+    <trkpt lat="47.2" lon="8.2"><ele>1300</ele></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+
+    /// The fixture track as an editor saves it in the encoding of `bom`.
+    fn fixture(bom: Bom) -> Vec<u8> {
+        let utf16 = TRACK.replace("UTF-8", "UTF-16");
+        let utf32 = TRACK.replace("UTF-8", "UTF-32");
+        let mut data = match bom {
+            Bom::Utf16Le => vec![0xff, 0xfe],
+            Bom::Utf16Be => vec![0xfe, 0xff],
+            Bom::Utf32Le => vec![0xff, 0xfe, 0, 0],
+            Bom::Utf32Be => vec![0, 0, 0xfe, 0xff],
+            _ => vec![0xef, 0xbb, 0xbf],
+        };
+        match bom {
+            Bom::Utf16Le => data.extend(utf16.encode_utf16().flat_map(u16::to_le_bytes)),
+            Bom::Utf16Be => data.extend(utf16.encode_utf16().flat_map(u16::to_be_bytes)),
+            Bom::Utf32Le => data.extend(utf32.chars().flat_map(|c| u32::from(c).to_le_bytes())),
+            Bom::Utf32Be => data.extend(utf32.chars().flat_map(|c| u32::from(c).to_be_bytes())),
+            _ => data.extend(TRACK.bytes()),
+        }
+        data
+    }
+
+    fn assert_loads(bom: Bom) {
+        let data = decode(fixture(bom)).unwrap();
+        assert_eq!(data, TRACK.as_bytes());
+        let gpx = load(&data, TrackFormat::Gpx).unwrap();
+        assert_eq!(gpx.tracks[0].name.as_deref(), Some("Höhenweg ⛰"));
+        assert_eq!(gpx.tracks[0].segments[0].points.len(), 2);
+    }
+
+    #[test]
+    fn utf8_mark_is_dropped() {
+        assert_loads(Bom::Utf8);
+    }
+
+    #[test]
+    fn utf16_is_transcoded() {
+        assert_loads(Bom::Utf16Le);
+        assert_loads(Bom::Utf16Be);
+    }
+
+    #[test]
+    fn utf32_is_transcoded() {
+        assert_loads(Bom::Utf32Le);
+        assert_loads(Bom::Utf32Be);
+    }
+
+    #[test]
+    fn plain_and_broken_files() {
+        assert_eq!(decode(TRACK.into()).unwrap(), TRACK.as_bytes());
+        // Odd length, unpaired surrogate, UTF-7
+        assert!(decode(vec![0xff, 0xfe, b'<']).is_err());
+        assert!(decode(vec![0xff, 0xfe, 0x00, 0xd8, b'<', 0]).is_err());
+        assert!(decode(vec![0x2b, 0x2f, 0x76, 0x38, b'<']).is_err());
+    }
+
+    #[test]
+    fn declared_encoding_is_replaced() {
+        let declare = |text: &str| declare_utf8(text.to_string());
+        assert_eq!(
+            declare("<?xml version='1.0' encoding='utf-16' ?><gpx/>"),
+            "<?xml version='1.0' encoding='UTF-8' ?><gpx/>"
+        );
+        assert_eq!(
+            declare("<?xml version=\"1.0\"?><gpx/>"),
+            "<?xml version=\"1.0\"?><gpx/>"
+        );
+        assert_eq!(declare("<gpx encoding=\"x\"/>"), "<gpx encoding=\"x\"/>");
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+use std::io;
+
+use crate::TrackFormat;
+
+/// Why a track file could not be loaded. The messages say what to do about
+/// it, the path of the file is left to the caller.
+#[derive(Debug)]
+pub enum LoadError {
+    NotFound,
+    Io(io::Error),
+    /// Neither the content nor the extension tell the format.
+    UnknownFormat,
+    /// The byte order mark names an unsupported encoding or the content does
+    /// not follow it.
+    Encoding(String),
+    Parse {
+        format: TrackFormat,
+        /// 1-based, if the parser tells it.
+        line: Option<u64>,
+        message: String,
+    },
+    /// Neither tracks nor routes have points, so there is no area to load.
+    NoPoints,
+    OutOfRange {
+        lat: f64,
+        lon: f64,
+    },
+}
+
+impl LoadError {
+    pub(crate) fn parse(format: TrackFormat, message: impl Into<String>) -> Self {
+        Self::Parse {
+            format,
+            line: None,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn xml(format: TrackFormat, error: &xml::reader::Error) -> Self {
+        use xml::common::Position;
+
+        Self::Parse {
+            format,
+            line: Some(error.position().row + 1),
+            message: error.msg().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "file not found, check the path"),
+            Self::Io(e) => write!(f, "cannot read the file: {e}"),
+            Self::UnknownFormat => write!(
+                f,
+                "unknown track format, use a .gpx, .tcx, .fit, .kml or .geojson file"
+            ),
+            Self::Encoding(e) => write!(f, "{e}, save the file as UTF-8"),
+            Self::Parse {
+                format,
+                line: Some(line),
+                message,
+            } => write!(f, "invalid {format} file on line {line}: {message}"),
+            Self::Parse {
+                format,
+                line: None,
+                message,
+            } => write!(f, "invalid {format} file: {message}"),
+            Self::NoPoints => write!(
+                f,
+                "no track or route points, export the track with its points"
+            ),
+            Self::OutOfRange { lat, lon } => write!(
+                f,
+                "point lat {lat} lon {lon} is out of range, latitudes lie within ±90° and \
+                 longitudes within ±180°; are they swapped?"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        if error.kind() == io::ErrorKind::NotFound {
+            Self::NotFound
+        } else {
+            Self::Io(error)
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use gpx::Gpx;
+use time::OffsetDateTime;
+
+use crate::load::{empty_gpx, track, PointBuilder};
+use crate::{LoadError, TrackFormat};
+
+/// Global message number of a track point.
+const RECORD: u16 = 20;
+/// Global message number of a course, holds its name.
+const COURSE: u16 = 31;
+/// FIT times count seconds from 1989-12-31T00:00:00Z.
+const FIT_EPOCH: i64 = 631_065_600;
+/// Positions are in semicircles, 2^31 of them make 180°.
+const SEMICIRCLE: f64 = 180.0 / 2_147_483_648.0;
+
+/// The layout of a local message type.
+struct Definition {
+    big_endian: bool,
+    global: u16,
+    /// Field number, size in bytes and base type.
+    fields: Vec<(u8, usize, u8)>,
+    /// Bytes of developer fields, skipped.
+    developer_size: usize,
+}
+
+/// Reads the record messages of a FIT activity or course as one track.
+pub fn read(data: &[u8]) -> Result<Gpx, LoadError> {
+    if data.len() < 12 || &data[8..12] != b".FIT" {
+        return Err(error("no FIT header"));
+    }
+    let header_len = usize::from(data[0]);
+    let data_len = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    let end = (header_len + data_len).min(data.len());
+    let mut reader = Reader {
+        data: &data[..end],
+        pos: header_len,
+    };
+
+    let mut definitions: Vec<Option<Definition>> = (0..16).map(|_| None).collect();
+    let mut timestamp = 0_u32;
+    let mut name = None;
+    let mut points = Vec::new();
+    while reader.pos < end {
+        let header = reader.bytes(1)?[0];
+        if header & 0x40 != 0 && header & 0x80 == 0 {
+            let local = usize::from(header & 0x0f);
+            definitions[local] = Some(read_definition(&mut reader, header & 0x20 != 0)?);
+            continue;
+        }
+
+        let (local, compressed_time) = if header & 0x80 != 0 {
+            // Compressed timestamp header, the low 5 bits of the time
+            let offset = u32::from(header & 0x1f);
+            timestamp += offset.wrapping_sub(timestamp & 0x1f) & 0x1f;
+            (usize::from((header >> 5) & 0x03), Some(timestamp))
+        } else {
+            (usize::from(header & 0x0f), None)
+        };
+        let definition = definitions[local]
+            .as_ref()
+            .ok_or_else(|| error(format!("message of undefined type {local}")))?;
+        let mut point = PointBuilder::default();
+        let mut time = compressed_time;
+        for (number, size, base_type) in &definition.fields {
+            let bytes = reader.bytes(*size)?;
+            let value = || uint(bytes, definition.big_endian, *base_type);
+            match (definition.global, number, size) {
+                (_, 253, 4) => {
+                    if let Some(t) = value() {
+                        timestamp = t;
+                        time = Some(t);
+                    }
+                }
+                (RECORD, 0, 4) => point.lat = value().map(semicircles),
+                (RECORD, 1, 4) => point.lon = value().map(semicircles),
+                (RECORD, 2, 2) | (RECORD, 78, 4) => {
+                    point.elevation = value().map(|v| f64::from(v) / 5.0 - 500.0);
+                }
+                (COURSE, 5, _) => {
+                    let text = bytes.split(|b| *b == 0).next().unwrap_or_default();
+                    name = Some(String::from_utf8_lossy(text).into_owned());
+                }
+                _ => {}
+            }
+        }
+        reader.bytes(definition.developer_size)?;
+
+        if definition.global == RECORD {
+            point.time = time
+                .and_then(|t| OffsetDateTime::from_unix_timestamp(FIT_EPOCH + i64::from(t)).ok())
+                .map(Into::into);
+            points.extend(point.build());
+        }
+    }
+
+    let mut gpx = empty_gpx();
+    if !points.is_empty() {
+        gpx.tracks.push(track(name, vec![points]));
+    }
+    Ok(gpx)
+}
+
+fn read_definition(reader: &mut Reader, developer: bool) -> Result<Definition, LoadError> {
+    let fixed = reader.bytes(5)?;
+    let big_endian = fixed[1] == 1;
+    let global = if big_endian {
+        u16::from_be_bytes([fixed[2], fixed[3]])
+    } else {
+        u16::from_le_bytes([fixed[2], fixed[3]])
+    };
+    let count = usize::from(fixed[4]);
+    let fields = reader
+        .bytes(3 * count)?
+        .chunks(3)
+        .map(|field| (field[0], usize::from(field[1]), field[2]))
+        .collect();
+    let developer_size = if developer {
+        let count = usize::from(reader.bytes(1)?[0]);
+        reader
+            .bytes(3 * count)?
+            .chunks(3)
+            .map(|field| usize::from(field[1]))
+            .sum()
+    } else {
+        0
+    };
+    Ok(Definition {
+        big_endian,
+        global,
+        fields,
+        developer_size,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| error(format!("file ends inside a message at byte {}", self.pos)))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+fn error(message: impl Into<String>) -> LoadError {
+    LoadError::parse(TrackFormat::Fit, message)
+}
+
+/// The bits of an integer of 1 to 4 bytes, `None` for the invalid value of
+/// its base type.
+fn uint(bytes: &[u8], big_endian: bool, base_type: u8) -> Option<u32> {
+    let mut value = 0_u32;
+    let mut all_set = 0_u32;
+    for i in 0..bytes.len().min(4) {
+        let byte = if big_endian {
+            bytes[i]
+        } else {
+            bytes[bytes.len() - 1 - i]
+        };
+        value = value << 8 | u32::from(byte);
+        all_set = all_set << 8 | 0xff;
+    }
+    let invalid = match base_type & 0x1f {
+        // sint8, sint16, sint32: the largest positive value
+        1 | 3 | 5 => all_set >> 1,
+        // uint8z, uint16z, uint32z
+        10..=12 => 0,
+        _ => all_set,
+    };
+    (value != invalid).then_some(value)
+}
+
+/// Degrees of a signed 32 bit semicircle value.
+fn semicircles(value: u32) -> f64 {
+    f64::from(i32::from_ne_bytes(value.to_ne_bytes())) * SEMICIRCLE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpx::Waypoint;
+
+    /// A FIT file with one record definition and two records, the second
+    /// with a compressed timestamp.
+    #[allow(clippy::cast_possible_truncation)]
+    fn activity() -> Vec<u8> {
+        let degrees = |d: f64| ((d / SEMICIRCLE) as i32).to_le_bytes();
+        let mut records = vec![
+            0x40, 0, 0, 20, 0, 4, // definition of local type 0: record
+            253, 4, 0x86, 0, 4, 0x85, 1, 4, 0x85, 2, 2, 0x84,
+        ];
+        records.push(0x00);
+        records.extend(1_000_000_000_u32.to_le_bytes());
+        records.extend(degrees(47.5));
+        records.extend(degrees(8.25));
+        records.extend(((1000 + 500) * 5_u16).to_le_bytes());
+        records.extend([0x40 | 0x01, 0, 0, 20, 0, 2, 0, 4, 0x85, 1, 4, 0x85]);
+        // Compressed header for local type 1, 3 seconds later: the low bits
+        // of 1_000_000_000 are zero
+        records.push(0x80 | (1 << 5) | 3);
+        records.extend(degrees(-33.75));
+        records.extend(degrees(151.0));
+        // Logged before the first fix, both coordinates invalid
+        records.push(0x01);
+        records.extend(0x7fff_ffff_u32.to_le_bytes());
+        records.extend(0x7fff_ffff_u32.to_le_bytes());
+
+        let mut file = vec![14, 0x10, 0x52, 0x08];
+        file.extend(u32::try_from(records.len()).unwrap().to_le_bytes());
+        file.extend(b".FIT");
+        file.extend([0, 0]);
+        file.extend(records);
+        file.extend([0, 0]);
+        file
+    }
+
+    #[test]
+    fn records_become_track_points() {
+        let gpx = read(&activity()).unwrap();
+        let points = &gpx.tracks[0].segments[0].points;
+        // The record without a fix is skipped
+        assert_eq!(points.len(), 2);
+        assert!((points[0].point().y() - 47.5).abs() < 1e-6);
+        assert!((points[0].point().x() - 8.25).abs() < 1e-6);
+        assert_eq!(points[0].elevation, Some(1000.0));
+        assert!((points[1].point().y() + 33.75).abs() < 1e-6);
+        assert_eq!(points[1].elevation, None);
+        assert!(points.iter().all(|p| p.point().x() < 180.0));
+
+        let time = |p: &Waypoint| OffsetDateTime::from(p.time.unwrap()).unix_timestamp();
+        assert_eq!(time(&points[0]), FIT_EPOCH + 1_000_000_000);
+        assert_eq!(time(&points[1]), FIT_EPOCH + 1_000_000_003);
+    }
+
+    #[test]
+    fn truncated_files_fail() {
+        let mut data = activity();
+        data.truncate(30);
+        // The header still claims the full length
+        assert!(read(&data).is_err());
+        assert!(read(b"not a fit file").is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use gpx::{Gpx, Waypoint};
+use serde_json::Value;
+
+use crate::load::{empty_gpx, track, PointBuilder};
+use crate::{LoadError, TrackFormat};
+
+/// Reads the geometries of a `FeatureCollection`, a `Feature` or a bare
+/// geometry: lines become tracks and points become waypoints, named after
+/// the `name` property of their feature.
+pub fn read(data: &[u8]) -> Result<Gpx, LoadError> {
+    let value: Value = serde_json::from_slice(data).map_err(|e| {
+        let message = e.to_string();
+        LoadError::Parse {
+            format: TrackFormat::GeoJson,
+            line: Some(e.line() as u64),
+            // Without the position serde_json appends
+            message: message.split(" at line ").next().unwrap_or_default().into(),
+        }
+    })?;
+    let mut gpx = empty_gpx();
+    match value["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in value["features"].as_array().into_iter().flatten() {
+                read_feature(feature, &mut gpx);
+            }
+        }
+        Some("Feature") => read_feature(&value, &mut gpx),
+        Some(_) => read_geometry(&value, None, &mut gpx),
+        None => {
+            let message = "no \"type\" member, not a GeoJSON object";
+            return Err(LoadError::parse(TrackFormat::GeoJson, message));
+        }
+    }
+    Ok(gpx)
+}
+
+fn read_feature(feature: &Value, gpx: &mut Gpx) {
+    let name = feature["properties"]["name"].as_str().map(String::from);
+    read_geometry(&feature["geometry"], name, gpx);
+}
+
+fn read_geometry(geometry: &Value, name: Option<String>, gpx: &mut Gpx) {
+    let coordinates = &geometry["coordinates"];
+    match geometry["type"].as_str() {
+        Some("Point") => gpx.waypoints.extend(position(coordinates, name)),
+        Some("MultiPoint") => {
+            for p in coordinates.as_array().into_iter().flatten() {
+                gpx.waypoints.extend(position(p, name.clone()));
+            }
+        }
+        Some("LineString") => gpx.tracks.push(track(name, vec![line(coordinates)])),
+        Some("MultiLineString") => {
+            let lines = coordinates.as_array().into_iter().flatten().map(line);
+            gpx.tracks.push(track(name, lines.collect()));
+        }
+        Some("GeometryCollection") => {
+            for g in geometry["geometries"].as_array().into_iter().flatten() {
+                read_geometry(g, name.clone(), gpx);
+            }
+        }
+        // Polygons are areas, not tracks
+        _ => {}
+    }
+}
+
+fn line(coordinates: &Value) -> Vec<Waypoint> {
+    coordinates
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| position(p, None))
+        .collect()
+}
+
+/// A `[lon, lat]` or `[lon, lat, elevation]` position.
+fn position(coordinates: &Value, name: Option<String>) -> Option<Waypoint> {
+    PointBuilder {
+        lon: coordinates[0].as_f64(),
+        lat: coordinates[1].as_f64(),
+        elevation: coordinates[2].as_f64(),
+        name,
+        ..PointBuilder::default()
+    }
+    .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEATURES: &str = r#"{
+  "type": "FeatureCollection",
+  "features": [
+    {"type": "Feature", "properties": {"name": "Tour"},
+     "geometry": {"type": "MultiLineString", "coordinates": [
+       [[8.1, 47.1, 400], [8.2, 47.2, 410]],
+       [[8.3, 47.3]]
+     ]}},
+    {"type": "Feature", "properties": {"name": "Spring"},
+     "geometry": {"type": "Point", "coordinates": [8.15, 47.15]}},
+    {"type": "Feature", "properties": null,
+     "geometry": {"type": "Polygon", "coordinates": [[[8, 47], [9, 47], [9, 48], [8, 47]]]}}
+  ]
+}"#;
+
+    #[test]
+    fn features_become_tracks_and_waypoints() {
+        let gpx = read(FEATURES.as_bytes()).unwrap();
+        assert_eq!(gpx.tracks.len(), 1);
+        assert_eq!(gpx.tracks[0].name.as_deref(), Some("Tour"));
+        let segments = &gpx.tracks[0].segments;
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].points[1].elevation, Some(410.0));
+        assert_eq!(segments[1].points.len(), 1);
+        assert_eq!(gpx.waypoints.len(), 1);
+        assert_eq!(gpx.waypoints[0].name.as_deref(), Some("Spring"));
+    }
+
+    #[test]
+    fn bare_geometry_and_bad_json() {
+        let gpx =
+            read(br#"{"type": "LineString", "coordinates": [[8, 47], [8.1, 47.1]]}"#).unwrap();
+        assert_eq!(gpx.tracks[0].segments[0].points.len(), 2);
+        assert!(read(b"{\"features\": []}").is_err());
+        assert!(read(b"{").is_err());
+    }
+}
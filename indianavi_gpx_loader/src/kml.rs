@@ -0,0 +1,153 @@
+use gpx::{Gpx, Waypoint};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::load::{empty_gpx, parse_time, track, PointBuilder};
+use crate::{LoadError, TrackFormat};
+
+/// The lines and points of one `<Placemark>`.
+#[derive(Default)]
+struct Placemark {
+    name: Option<String>,
+    lines: Vec<Vec<Waypoint>>,
+    points: Vec<Waypoint>,
+}
+
+/// Reads the placemarks of a KML file: line strings and `gx:Track`s become
+/// tracks, one per placemark, and points become waypoints.
+pub fn read(data: &[u8]) -> Result<Gpx, LoadError> {
+    let mut gpx = empty_gpx();
+    let mut path: Vec<String> = Vec::new();
+    let mut text = String::new();
+    let mut placemark: Option<Placemark> = None;
+    // Times and points of the gx:Track being read
+    let mut whens = Vec::new();
+    let mut timed: Vec<Waypoint> = Vec::new();
+    for event in EventReader::new(data) {
+        match event.map_err(|e| LoadError::xml(TrackFormat::Kml, &e))? {
+            XmlEvent::StartElement { name, .. } => {
+                match name.local_name.as_str() {
+                    "Placemark" => placemark = Some(Placemark::default()),
+                    "Track" => {
+                        whens.clear();
+                        timed.clear();
+                    }
+                    _ => {}
+                }
+                path.push(name.local_name);
+                text.clear();
+            }
+            XmlEvent::Characters(s) => text.push_str(&s),
+            XmlEvent::EndElement { name } => {
+                path.pop();
+                let parent = path.last().map(String::as_str);
+                let Some(current) = placemark.as_mut() else {
+                    continue;
+                };
+                match (name.local_name.as_str(), parent) {
+                    ("name", Some("Placemark")) => current.name = Some(text.trim().to_string()),
+                    ("coordinates", Some("LineString")) => {
+                        current.lines.push(coordinates(&text, ','));
+                    }
+                    ("coordinates", Some("Point")) => {
+                        current.points.extend(coordinates(&text, ','));
+                    }
+                    ("when", _) => whens.push(text.trim().to_string()),
+                    ("coord", _) => {
+                        if let Some(mut p) = coordinates(&text, ' ').pop() {
+                            p.time = whens.get(timed.len()).and_then(|when| parse_time(when));
+                            timed.push(p);
+                        }
+                    }
+                    ("Track", _) => current.lines.push(std::mem::take(&mut timed)),
+                    ("Placemark", _) => {
+                        let Placemark {
+                            name,
+                            lines,
+                            points,
+                        } = placemark.take().expect("a placemark");
+                        for mut point in points {
+                            point.name.clone_from(&name);
+                            gpx.waypoints.push(point);
+                        }
+                        if !lines.is_empty() {
+                            gpx.tracks.push(track(name, lines));
+                        }
+                    }
+                    _ => {}
+                }
+                text.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(gpx)
+}
+
+/// Points of a `lon,lat[,ele]` list separated by whitespace, or of a single
+/// `lon lat ele` tuple for `separator` `' '`.
+fn coordinates(text: &str, separator: char) -> Vec<Waypoint> {
+    let tuples: Vec<&str> = if separator == ' ' {
+        vec![text.trim()]
+    } else {
+        text.split_whitespace().collect()
+    };
+    tuples
+        .into_iter()
+        .filter_map(|tuple| {
+            let mut values = tuple
+                .split(separator)
+                .filter(|v| !v.is_empty())
+                .map(|v| v.trim().parse::<f64>());
+            PointBuilder {
+                lon: values.next()?.ok(),
+                lat: values.next()?.ok(),
+                elevation: values.next().and_then(Result::ok),
+                ..PointBuilder::default()
+            }
+            .build()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLACEMARKS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+<Document>
+  <Placemark><name>Route</name>
+    <LineString><coordinates>
+      8.1,47.1,400 8.2,47.2,410
+      8.3,47.3
+    </coordinates></LineString>
+  </Placemark>
+  <Placemark><name>Hut</name><Point><coordinates>8.25,47.25,1200</coordinates></Point></Placemark>
+  <Placemark><name>Recorded</name>
+    <gx:Track>
+      <when>2024-05-01T10:00:00Z</when><when>2024-05-01T10:00:10Z</when>
+      <gx:coord>8.4 47.4 500</gx:coord><gx:coord>8.5 47.5 510</gx:coord>
+    </gx:Track>
+  </Placemark>
+</Document></kml>"#;
+
+    #[test]
+    fn placemarks_become_tracks_and_waypoints() {
+        let gpx = read(PLACEMARKS.as_bytes()).unwrap();
+        assert_eq!(gpx.tracks.len(), 2);
+        assert_eq!(gpx.tracks[0].name.as_deref(), Some("Route"));
+        let route = &gpx.tracks[0].segments[0].points;
+        assert_eq!(route.len(), 3);
+        assert_eq!(route[1].elevation, Some(410.0));
+        assert_eq!(route[2].elevation, None);
+
+        let recorded = &gpx.tracks[1].segments[0].points;
+        assert_eq!(recorded.len(), 2);
+        assert!((recorded[1].point().x() - 8.5).abs() < 1e-9);
+        assert!(recorded[1].time.is_some());
+
+        assert_eq!(gpx.waypoints.len(), 1);
+        assert_eq!(gpx.waypoints[0].name.as_deref(), Some("Hut"));
+        assert_eq!(gpx.waypoints[0].elevation, Some(1200.0));
+    }
+}
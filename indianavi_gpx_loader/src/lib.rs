@@ -5,25 +5,178 @@
 #![warn(clippy::all, clippy::nursery, clippy::pedantic)]
 #![allow(clippy::non_ascii_literal)]
 
-use gpx::Gpx;
+use gpx::{Gpx, GpxVersion, Track, TrackSegment, Waypoint};
 
+mod bom;
+mod error;
+mod fit;
+mod geojson;
+mod kml;
+mod load;
+mod outliers;
+mod select;
+mod simplify;
+mod stats;
+mod tcx;
+
+pub use bom::decode;
+pub use error::LoadError;
+pub use load::{load, load_file, validate, TrackFormat};
+pub use outliers::{filter_outliers, Outlier, OutlierLimits, Outliers};
+pub use select::{parse_end, parse_start, select, Selection};
+pub use simplify::{simplify, Simplify};
+pub use stats::{elevation_profile, haversine, segment_stats, SegmentStats, Stats};
+
+/// Mean earth radius in meters.
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+/// The `[min, max]` longitudes and latitudes of all track and route points.
+/// Without points the borders are inverted, `[180, -180]` and `[90, -90]`.
 pub fn calculate_boundaries(gpx: Gpx, _margin: u32) -> ([f64; 2], [f64; 2]) {
-    let mut lon_border: [f64; 2] = [90.0, -90.0];
-    let mut lat_border: [f64; 2] = [180.0, -180.0];
+    let mut points = gpx
+        .tracks
+        .iter()
+        .flat_map(|t| &t.segments)
+        .flat_map(|s| &s.points)
+        .chain(gpx.routes.iter().flat_map(|r| &r.points));
+    let Some(first) = points.next() else {
+        return ([180.0, -180.0], [90.0, -90.0]);
+    };
+    let (x, y) = (first.point().x(), first.point().y());
+    let mut lon_border: [f64; 2] = [x, x];
+    let mut lat_border: [f64; 2] = [y, y];
+    for p in points {
+        (lon_border, lat_border) = adjust_boundaries(p, lon_border, lat_border);
+    }
+
+    (lon_border, lat_border)
+}
+
+/// (lon, lat) points of every track segment and route, one line each.
+#[must_use]
+pub fn polylines(gpx: &Gpx) -> Vec<Vec<(f64, f64)>> {
+    let line = |points: &[gpx::Waypoint]| -> Vec<(f64, f64)> {
+        points
+            .iter()
+            .map(|p| (p.point().x(), p.point().y()))
+            .collect()
+    };
+    let mut lines = Vec::new();
     for track in &gpx.tracks {
         for s in &track.segments {
-            for p in &s.points {
-                (lon_border, lat_border) = adjust_boundaries(p, lon_border, lat_border);
-            }
+            lines.push(line(&s.points));
         }
     }
     for route in &gpx.routes {
-        for p in &route.points {
-            (lon_border, lat_border) = adjust_boundaries(p, lon_border, lat_border);
+        lines.push(line(&route.points));
+    }
+    lines
+}
+
+/// A waypoint of the GPX file such as a hut, a water source or a campsite.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Poi {
+    /// Name, numbered when the waypoint has none.
+    pub name: String,
+    /// Symbol name like `Campground`, if given.
+    pub symbol: Option<String>,
+    pub lon: f64,
+    pub lat: f64,
+}
+
+/// The `<wpt>` entries of the file in file order.
+#[must_use]
+pub fn pois(gpx: &Gpx) -> Vec<Poi> {
+    gpx.waypoints
+        .iter()
+        .enumerate()
+        .map(|(i, w)| Poi {
+            name: w
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("Waypoint {}", i + 1)),
+            symbol: w.symbol.clone(),
+            lon: w.point().x(),
+            lat: w.point().y(),
+        })
+        .collect()
+}
+
+/// Number of points in all tracks and routes.
+#[must_use]
+pub fn point_count(gpx: &Gpx) -> usize {
+    let tracks = gpx.tracks.iter().flat_map(|t| &t.segments);
+    tracks.map(|s| s.points.len()).sum::<usize>()
+        + gpx.routes.iter().map(|r| r.points.len()).sum::<usize>()
+}
+
+/// The track as the device reads it.
+///
+/// A single track with one segment that holds the points of every track and
+/// route in file order, with nothing but position, elevation and time. Each
+/// line is simplified with `method` and `tolerance` in meters, then the
+/// track is thinned to at most `max_points` points.
+#[must_use]
+pub fn device_track(gpx: &Gpx, method: Simplify, tolerance: f64, max_points: usize) -> Gpx {
+    let mut points: Vec<Waypoint> = Vec::new();
+    let mut name = None;
+    for track in &gpx.tracks {
+        name = name.or_else(|| track.name.clone());
+        for s in &track.segments {
+            points.extend(
+                simplify(&s.points, method, tolerance)
+                    .iter()
+                    .map(bare_point),
+            );
         }
     }
+    for route in &gpx.routes {
+        name = name.or_else(|| route.name.clone());
+        points.extend(
+            simplify(&route.points, method, tolerance)
+                .iter()
+                .map(bare_point),
+        );
+    }
 
-    (lon_border, lat_border)
+    let mut segment = TrackSegment::new();
+    segment.points = thin(points, max_points);
+    let mut track = Track::new();
+    track.name = name;
+    track.segments.push(segment);
+    Gpx {
+        version: GpxVersion::Gpx11,
+        creator: Some("IndiaNavi map downloader".to_string()),
+        tracks: vec![track],
+        ..Gpx::default()
+    }
+}
+
+fn bare_point(p: &Waypoint) -> Waypoint {
+    let mut point = Waypoint::new(p.point());
+    point.elevation = p.elevation;
+    point.time = p.time;
+    point
+}
+
+/// Keeps `max_points` evenly spread points, always the first and the last.
+fn thin(points: Vec<Waypoint>, max_points: usize) -> Vec<Waypoint> {
+    if points.len() <= max_points {
+        return points;
+    }
+    if max_points < 2 {
+        return points.into_iter().take(max_points).collect();
+    }
+    let last = points.len() - 1;
+    let mut keep = (0..max_points)
+        .map(|i| i * last / (max_points - 1))
+        .peekable();
+    points
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| keep.next_if_eq(i).is_some())
+        .map(|(_, p)| p)
+        .collect()
 }
 
 fn adjust_boundaries(
@@ -33,21 +186,11 @@ fn adjust_boundaries(
 ) -> ([f64; 2], [f64; 2]) {
     let x = p.point().x();
     let y = p.point().y();
-    if x < lon_border[0] {
-        lon_border[0] = x;
-    } else if x > lon_border[1] {
-        lon_border[1] = x;
-    }
-    if y < lat_border[0] {
-        lat_border[0] = y;
-    } else if y > lat_border[1] {
-        lat_border[1] = y;
-    }
+    lon_border = [lon_border[0].min(x), lon_border[1].max(x)];
+    lat_border = [lat_border[0].min(y), lat_border[1].max(y)];
     (lon_border, lat_border)
 }
 
-
-
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
@@ -61,4 +204,79 @@ mod tests {
         let result = add(2, 2);
         assert_eq!(result, 4);
     }
+
+    pub const TRACK_AND_ROUTE: &str = r#"<?xml version="1.0"?>
+<gpx version="1.1" creator="test">
+  <trk><name>Tour</name>
+    <trkseg>
+      <trkpt lat="47.0" lon="8.0"><ele>400</ele><extensions><hr>120</hr></extensions></trkpt>
+      <trkpt lat="47.1" lon="8.1"><ele>410</ele></trkpt>
+    </trkseg>
+    <trkseg><trkpt lat="47.2" lon="8.2"><ele>420</ele></trkpt></trkseg>
+  </trk>
+  <rte><name>Back</name>
+    <rtept lat="47.3" lon="8.3"><name>Hut</name></rtept>
+    <rtept lat="47.4" lon="8.4"></rtept>
+  </rte>
+</gpx>"#;
+
+    #[test]
+    fn boundaries_of_one_point_and_a_westward_track() {
+        use geo_types::Point;
+
+        let point = |lon: f64, lat: f64| gpx::Waypoint::new(Point::new(lon, lat));
+        let mut gpx = load::empty_gpx();
+        gpx.tracks
+            .push(load::track(None, vec![vec![point(8.0, 47.0)]]));
+        assert_eq!(calculate_boundaries(gpx, 0), ([8.0, 8.0], [47.0, 47.0]));
+
+        // Longitude and latitude only decrease
+        let mut gpx = load::empty_gpx();
+        let west = vec![point(-70.0, 40.0), point(-71.0, 39.5), point(-72.5, 39.0)];
+        gpx.tracks.push(load::track(None, vec![west]));
+        assert_eq!(calculate_boundaries(gpx, 0), ([-72.5, -70.0], [39.0, 40.0]));
+    }
+
+    #[test]
+    fn device_track_merges_segments_and_routes() {
+        let gpx = gpx::read(TRACK_AND_ROUTE.as_bytes()).unwrap();
+        let device = device_track(&gpx, Simplify::Off, 0.0, 100);
+        assert!(device.routes.is_empty());
+        assert_eq!(device.tracks.len(), 1);
+        assert_eq!(device.tracks[0].name.as_deref(), Some("Tour"));
+        assert_eq!(device.tracks[0].segments.len(), 1);
+
+        let points = &device.tracks[0].segments[0].points;
+        let lats: Vec<f64> = points.iter().map(|p| p.point().y()).collect();
+        assert_eq!(lats, vec![47.0, 47.1, 47.2, 47.3, 47.4]);
+        assert_eq!(points[0].elevation, Some(400.0));
+        assert_eq!(points[3].name, None);
+    }
+
+    #[test]
+    fn waypoints_become_pois() {
+        let gpx = gpx::read(
+            r#"<gpx version="1.1" creator="test">
+  <wpt lat="47.5" lon="8.5"><name>Hut</name><sym>Lodge</sym></wpt>
+  <wpt lat="47.6" lon="8.6"></wpt>
+</gpx>"#
+                .as_bytes(),
+        )
+        .unwrap();
+        let pois = pois(&gpx);
+        assert_eq!(pois.len(), 2);
+        assert_eq!(pois[0].name, "Hut");
+        assert_eq!(pois[0].symbol.as_deref(), Some("Lodge"));
+        assert!((pois[0].lat - 47.5).abs() < 1e-9);
+        assert_eq!(pois[1].name, "Waypoint 2");
+        assert_eq!(pois[1].symbol, None);
+    }
+
+    #[test]
+    fn device_track_keeps_ends_when_thinned() {
+        let gpx = gpx::read(TRACK_AND_ROUTE.as_bytes()).unwrap();
+        let points = &device_track(&gpx, Simplify::Off, 0.0, 3).tracks[0].segments[0].points;
+        let lats: Vec<f64> = points.iter().map(|p| p.point().y()).collect();
+        assert_eq!(lats, vec![47.0, 47.2, 47.4]);
+    }
 }
@@ -0,0 +1,262 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use geo_types::Point;
+use gpx::{Gpx, GpxVersion, Time, Track, TrackSegment, Waypoint};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::LoadError;
+
+/// File formats tracks are read from. All of them are turned into a `Gpx`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackFormat {
+    Gpx,
+    /// Garmin Training Center, exported by watches.
+    Tcx,
+    /// Garmin FIT activities and courses from bike computers.
+    Fit,
+    Kml,
+    GeoJson,
+}
+
+impl TrackFormat {
+    /// The format of a file, from its content or else from its extension.
+    #[must_use]
+    pub fn detect(path: &Path, data: &[u8]) -> Option<Self> {
+        Self::sniff(data).or_else(|| {
+            let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+            match extension.as_str() {
+                "json" => Some(Self::GeoJson),
+                other => other.parse().ok(),
+            }
+        })
+    }
+
+    fn sniff(data: &[u8]) -> Option<Self> {
+        if data.len() >= 12 && &data[8..12] == b".FIT" {
+            return Some(Self::Fit);
+        }
+        let start = String::from_utf8_lossy(&data[..data.len().min(1024)]);
+        let start = start.trim_start_matches(|c: char| c.is_whitespace() || c == '\u{feff}');
+        if start.starts_with('{') {
+            return Some(Self::GeoJson);
+        }
+        [
+            ("<gpx", Self::Gpx),
+            ("<TrainingCenterDatabase", Self::Tcx),
+            ("<kml", Self::Kml),
+        ]
+        .into_iter()
+        .find(|(root, _)| start.contains(root))
+        .map(|(_, format)| format)
+    }
+}
+
+impl FromStr for TrackFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gpx" => Ok(Self::Gpx),
+            "tcx" => Ok(Self::Tcx),
+            "fit" => Ok(Self::Fit),
+            "kml" => Ok(Self::Kml),
+            "geojson" => Ok(Self::GeoJson),
+            _ => Err(format!(
+                "unknown track format '{s}', use gpx, tcx, fit, kml or geojson"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TrackFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gpx => write!(f, "gpx"),
+            Self::Tcx => write!(f, "tcx"),
+            Self::Fit => write!(f, "fit"),
+            Self::Kml => write!(f, "kml"),
+            Self::GeoJson => write!(f, "geojson"),
+        }
+    }
+}
+
+/// Reads a track file of any known format.
+///
+/// # Errors
+///
+/// Fails if the data is not valid in the given format.
+pub fn load(data: &[u8], format: TrackFormat) -> Result<Gpx, LoadError> {
+    match format {
+        TrackFormat::Gpx => gpx::read(data).map_err(|e| match e {
+            gpx::errors::GpxError::XmlParseError(e) => LoadError::xml(format, &e),
+            e => LoadError::parse(format, e.to_string()),
+        }),
+        TrackFormat::Tcx => crate::tcx::read(data),
+        TrackFormat::Fit => crate::fit::read(data),
+        TrackFormat::Kml => crate::kml::read(data),
+        TrackFormat::GeoJson => crate::geojson::read(data),
+    }
+}
+
+/// Reads a track file, decoding its byte order mark and detecting its format,
+/// and checks it with [`validate`].
+///
+/// # Errors
+///
+/// Fails if the file cannot be read, its format is unknown, its content is
+/// not valid or it has no usable points.
+pub fn load_file(path: &Path) -> Result<Gpx, LoadError> {
+    let data = std::fs::read(path)?;
+    let data = if TrackFormat::sniff(&data) == Some(TrackFormat::Fit) {
+        data
+    } else {
+        crate::decode(data)?
+    };
+    let format = TrackFormat::detect(path, &data).ok_or(LoadError::UnknownFormat)?;
+    let gpx = load(&data, format)?;
+    validate(&gpx)?;
+    Ok(gpx)
+}
+
+/// Checks that the tracks or routes have points to take the area from and
+/// that all coordinates lie on earth.
+///
+/// # Errors
+///
+/// [`LoadError::NoPoints`] or [`LoadError::OutOfRange`] for the first bad
+/// point.
+pub fn validate(gpx: &Gpx) -> Result<(), LoadError> {
+    let lines = gpx
+        .tracks
+        .iter()
+        .flat_map(|t| &t.segments)
+        .flat_map(|s| &s.points)
+        .chain(gpx.routes.iter().flat_map(|r| &r.points));
+    if lines.clone().next().is_none() {
+        return Err(LoadError::NoPoints);
+    }
+    for point in lines.chain(&gpx.waypoints) {
+        let (lon, lat) = point.point().x_y();
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(LoadError::OutOfRange { lat, lon });
+        }
+    }
+    Ok(())
+}
+
+/// An empty `Gpx` the readers of other formats fill.
+pub fn empty_gpx() -> Gpx {
+    Gpx {
+        version: GpxVersion::Gpx11,
+        ..Gpx::default()
+    }
+}
+
+/// A track of the given name and segments.
+pub fn track(name: Option<String>, segments: Vec<Vec<Waypoint>>) -> Track {
+    let mut track = Track::new();
+    track.name = name;
+    track.segments = segments
+        .into_iter()
+        .map(|points| {
+            let mut segment = TrackSegment::new();
+            segment.points = points;
+            segment
+        })
+        .collect();
+    track
+}
+
+/// An RFC 3339 time like `2024-05-01T10:00:00Z`.
+pub fn parse_time(text: &str) -> Option<Time> {
+    OffsetDateTime::parse(text.trim(), &Rfc3339)
+        .ok()
+        .map(Into::into)
+}
+
+/// A point collected from the parts of a track point element.
+#[derive(Default)]
+pub struct PointBuilder {
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub elevation: Option<f64>,
+    pub time: Option<Time>,
+    pub name: Option<String>,
+}
+
+impl PointBuilder {
+    /// The waypoint, if a position was given.
+    pub fn build(self) -> Option<Waypoint> {
+        let mut point = Waypoint::new(Point::new(self.lon?, self.lat?));
+        point.elevation = self.elevation;
+        point.time = self.time;
+        point.name = self.name;
+        Some(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_are_found_by_content_then_extension() {
+        let detect = |name: &str, data: &str| TrackFormat::detect(Path::new(name), data.as_bytes());
+        assert_eq!(
+            detect("a.xml", "\u{feff}<?xml?>\n<gpx>"),
+            Some(TrackFormat::Gpx)
+        );
+        assert_eq!(
+            detect("a.gpx", "<?xml?><TrainingCenterDatabase>"),
+            Some(TrackFormat::Tcx)
+        );
+        assert_eq!(detect("a", " <kml xmlns=''>"), Some(TrackFormat::Kml));
+        assert_eq!(detect("a", "\n{\"type\": "), Some(TrackFormat::GeoJson));
+        assert_eq!(
+            detect("a", "\x0e\x10\0\0\0\0\0\0.FIT"),
+            Some(TrackFormat::Fit)
+        );
+        assert_eq!(detect("a.GeoJSON", ""), Some(TrackFormat::GeoJson));
+        assert_eq!(detect("a.TCX", "garbage"), Some(TrackFormat::Tcx));
+        assert_eq!(detect("a.txt", "garbage"), None);
+    }
+
+    #[test]
+    fn errors_tell_what_is_wrong() {
+        let missing = load_file(Path::new("/nonexistent/track.gpx"));
+        assert!(matches!(missing, Err(LoadError::NotFound)));
+
+        let broken = "<?xml version=\"1.0\"?>\n<gpx version=\"1.1\">\n<trk></gpx>";
+        match load(broken.as_bytes(), TrackFormat::Gpx) {
+            Err(LoadError::Parse { line, .. }) => assert_eq!(line, Some(3)),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+        match load(b"{\n\"type\": }", TrackFormat::GeoJson) {
+            Err(e @ LoadError::Parse { .. }) => {
+                assert!(e
+                    .to_string()
+                    .starts_with("invalid geojson file on line 2: "));
+            }
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn tracks_need_points_on_earth() {
+        let mut gpx = empty_gpx();
+        gpx.waypoints.push(Waypoint::new(Point::new(8.0, 47.0)));
+        assert!(matches!(validate(&gpx), Err(LoadError::NoPoints)));
+
+        let swapped = Waypoint::new(Point::new(47.0, 120.0));
+        gpx.tracks.push(track(None, vec![vec![swapped]]));
+        assert!(matches!(
+            validate(&gpx),
+            Err(LoadError::OutOfRange { lat, .. }) if (lat - 120.0).abs() < 1e-9
+        ));
+        gpx.tracks[0].segments[0].points[0] = Waypoint::new(Point::new(120.0, 47.0));
+        assert!(validate(&gpx).is_ok());
+    }
+}
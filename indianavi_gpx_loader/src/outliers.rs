@@ -0,0 +1,315 @@
+use std::fmt;
+use std::str::FromStr;
+
+use gpx::{Gpx, Waypoint};
+use time::OffsetDateTime;
+
+use crate::haversine;
+use crate::stats::line_names;
+
+/// Runs of up to this many points between two jumps are taken for spikes.
+const MAX_SPIKE_POINTS: usize = 5;
+
+/// What to do with bad GPS fixes in a recorded track.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Outliers {
+    Off,
+    /// List them but keep them.
+    #[default]
+    Warn,
+    Drop,
+}
+
+impl FromStr for Outliers {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "drop" => Ok(Self::Drop),
+            _ => Err(format!(
+                "unknown outlier handling '{s}', use off, warn or drop"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Outliers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::Warn => write!(f, "warn"),
+            Self::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+/// How far a receiver plausibly gets from one track point to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutlierLimits {
+    /// Meters per second between points with times.
+    pub max_speed: f64,
+    /// Meters between points without times.
+    pub max_jump: f64,
+}
+
+impl OutlierLimits {
+    fn exceeded(&self, a: &Waypoint, b: &Waypoint) -> bool {
+        let distance = haversine(a.point(), b.point());
+        let seconds = match (a.time, b.time) {
+            (Some(a), Some(b)) => (OffsetDateTime::from(b) - OffsetDateTime::from(a))
+                .as_seconds_f64()
+                .abs(),
+            _ => 0.0,
+        };
+        if seconds > 0.0 {
+            distance / seconds > self.max_speed
+        } else {
+            distance > self.max_jump
+        }
+    }
+}
+
+/// A track point taken for a bad GPS fix.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outlier {
+    /// Name of the segment, as in [`crate::segment_stats`].
+    pub line: String,
+    /// 1-based number of the point in its line.
+    pub number: usize,
+    pub lon: f64,
+    pub lat: f64,
+}
+
+/// Finds the bad fixes of every track segment and, for [`Outliers::Drop`],
+/// removes them. Routes are planned, not recorded, and their points may lie
+/// far apart, so they are left alone.
+///
+/// Points at 0,0 are bad fixes. Every segment is split into runs where the
+/// next point is out of reach within `limits`. The longest run is kept, and
+/// from there outwards a short run that cannot be reached is a spike if the
+/// track returns to within reach after it, or if it ends the segment, like a
+/// bad first fix before the receiver locks on.
+pub fn filter_outliers(gpx: &mut Gpx, limits: &OutlierLimits, mode: Outliers) -> Vec<Outlier> {
+    if mode == Outliers::Off {
+        return Vec::new();
+    }
+    // Track segments come first, the names of the routes are not used
+    let names = line_names(gpx);
+    let lines = gpx
+        .tracks
+        .iter_mut()
+        .flat_map(|t| &mut t.segments)
+        .map(|s| &mut s.points);
+
+    let mut outliers = Vec::new();
+    for (points, name) in lines.zip(names) {
+        let bad = line_outliers(points, limits);
+        outliers.extend(bad.iter().map(|&i| Outlier {
+            line: name.clone(),
+            number: i + 1,
+            lon: points[i].point().x(),
+            lat: points[i].point().y(),
+        }));
+        if mode == Outliers::Drop {
+            let mut bad = bad.into_iter().peekable();
+            let mut i = 0;
+            points.retain(|_| {
+                i += 1;
+                bad.next_if_eq(&(i - 1)).is_none()
+            });
+        }
+    }
+    outliers
+}
+
+/// Indices of the bad fixes of one line, ascending.
+fn line_outliers(points: &[Waypoint], limits: &OutlierLimits) -> Vec<usize> {
+    let null_island = |p: &Waypoint| p.point().x().abs() < 1e-9 && p.point().y().abs() < 1e-9;
+    let mut outliers: Vec<usize> = (0..points.len())
+        .filter(|&i| null_island(&points[i]))
+        .collect();
+
+    let jump = |a: usize, b: usize| limits.exceeded(&points[a], &points[b]);
+    let mut runs: Vec<Vec<usize>> = Vec::new();
+    for i in (0..points.len()).filter(|&i| !null_island(&points[i])) {
+        match runs.last_mut() {
+            Some(run) if !jump(run[run.len() - 1], i) => run.push(i),
+            _ => runs.push(vec![i]),
+        }
+    }
+
+    // The longest run is kept, the others are checked from there outwards
+    let Some(anchor) = (0..runs.len()).max_by_key(|&k| runs[k].len()) else {
+        return outliers;
+    };
+    let after: Vec<&[usize]> = runs[anchor + 1..].iter().map(Vec::as_slice).collect();
+    let before: Vec<&[usize]> = runs[..anchor].iter().rev().map(Vec::as_slice).collect();
+    let anchor = &runs[anchor];
+    outliers.extend(spikes(
+        points,
+        limits,
+        anchor[anchor.len() - 1],
+        &after,
+        true,
+    ));
+    outliers.extend(spikes(points, limits, anchor[0], &before, false));
+    outliers.sort_unstable();
+    outliers
+}
+
+/// The points of the runs that are spikes, walking away from the `kept`
+/// point, `forward` or backward through the line.
+fn spikes(
+    points: &[Waypoint],
+    limits: &OutlierLimits,
+    mut kept: usize,
+    runs: &[&[usize]],
+    forward: bool,
+) -> Vec<usize> {
+    let near = |run: &[usize]| if forward { run[0] } else { run[run.len() - 1] };
+    let far = |run: &[usize]| if forward { run[run.len() - 1] } else { run[0] };
+    let mut spikes = Vec::new();
+    for (k, run) in runs.iter().enumerate() {
+        let reached = |i: usize| !limits.exceeded(&points[kept], &points[i]);
+        let spike = !reached(near(run))
+            && run.len() <= MAX_SPIKE_POINTS
+            && runs.get(k + 1).is_none_or(|next| reached(near(next)));
+        if spike {
+            spikes.extend_from_slice(run);
+        } else {
+            kept = far(run);
+        }
+    }
+    spikes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::{empty_gpx, track};
+    use geo_types::Point;
+
+    const LIMITS: OutlierLimits = OutlierLimits {
+        max_speed: 50.0,
+        max_jump: 2000.0,
+    };
+
+    /// Track points a minute apart.
+    fn walk(lon_lat: &[(f64, f64)]) -> Vec<Waypoint> {
+        lon_lat
+            .iter()
+            .zip(0_i64..)
+            .map(|(&(lon, lat), minute)| {
+                let mut point = Waypoint::new(Point::new(lon, lat));
+                point.time = OffsetDateTime::from_unix_timestamp(1_700_000_000 + minute * 60)
+                    .ok()
+                    .map(Into::into);
+                point
+            })
+            .collect()
+    }
+
+    /// Positions about 110 m apart going north.
+    fn north(count: u32) -> Vec<(f64, f64)> {
+        (0..count)
+            .map(|i| (8.0, 0.001_f64.mul_add(f64::from(i), 47.0)))
+            .collect()
+    }
+
+    #[test]
+    fn spikes_and_null_island_are_dropped() {
+        let mut line = north(10);
+        line[3] = (0.0, 0.0);
+        line[6] = (-70.0, 40.0);
+        line[7] = (-70.001, 40.001);
+        let mut gpx = empty_gpx();
+        gpx.tracks
+            .push(track(Some("Walk".into()), vec![walk(&line)]));
+
+        let outliers = filter_outliers(&mut gpx, &LIMITS, Outliers::Drop);
+        let numbers: Vec<usize> = outliers.iter().map(|o| o.number).collect();
+        assert_eq!(numbers, [4, 7, 8]);
+        assert_eq!(outliers[0].line, "Walk");
+        assert_eq!(gpx.tracks[0].segments[0].points.len(), 7);
+        assert!((gpx.tracks[0].segments[0].points[5].point().y() - 47.008).abs() < 1e-9);
+    }
+
+    #[test]
+    fn warn_keeps_the_points() {
+        let mut line = north(8);
+        line[4] = (120.0, -30.0);
+        let mut gpx = empty_gpx();
+        gpx.tracks.push(track(None, vec![walk(&line)]));
+
+        let outliers = filter_outliers(&mut gpx, &LIMITS, Outliers::Warn);
+        assert_eq!(outliers.len(), 1);
+        assert_eq!(outliers[0].number, 5);
+        assert_eq!(gpx.tracks[0].segments[0].points.len(), 8);
+        assert!(filter_outliers(&mut gpx, &LIMITS, Outliers::Off).is_empty());
+    }
+
+    #[test]
+    fn bad_first_and_last_fixes_are_dropped() {
+        let mut line = north(8);
+        line[0] = (120.0, -30.0);
+        line[7] = (-70.0, 40.0);
+        let mut gpx = empty_gpx();
+        gpx.tracks.push(track(None, vec![walk(&line)]));
+
+        let outliers = filter_outliers(&mut gpx, &LIMITS, Outliers::Drop);
+        let numbers: Vec<usize> = outliers.iter().map(|o| o.number).collect();
+        assert_eq!(numbers, [1, 8]);
+        let points = &gpx.tracks[0].segments[0].points;
+        assert_eq!(points.len(), 6);
+        assert!((points[0].point().y() - 47.001).abs() < 1e-9);
+    }
+
+    #[test]
+    fn line_ends_and_routes_are_kept() {
+        // The last point is far away, but an hour later
+        let mut line = north(8);
+        line[7] = (8.5, 47.3);
+        let mut points = walk(&line);
+        points[7].time = OffsetDateTime::from_unix_timestamp(1_700_003_600)
+            .ok()
+            .map(Into::into);
+        let mut gpx = empty_gpx();
+        gpx.tracks.push(track(None, vec![points]));
+
+        // A sparse planned route, its last point 100 km from the one before
+        let mut route = gpx::Route::new();
+        for (lon, lat) in [(8.0, 47.0), (8.3, 47.0), (8.3, 47.25), (9.6, 47.25)] {
+            route.points.push(Waypoint::new(Point::new(lon, lat)));
+        }
+        gpx.routes.push(route);
+
+        assert!(filter_outliers(&mut gpx, &LIMITS, Outliers::Drop).is_empty());
+        assert_eq!(gpx.tracks[0].segments[0].points.len(), 8);
+        assert_eq!(gpx.routes[0].points.len(), 4);
+    }
+
+    #[test]
+    fn real_gaps_are_kept() {
+        // Untimed, the receiver was off on a train ride between two walks
+        let mut line = north(8);
+        line.extend(north(8).iter().map(|(lon, lat)| (lon + 1.0, *lat)));
+        let mut points = walk(&line);
+        for p in &mut points {
+            p.time = None;
+        }
+        let mut gpx = empty_gpx();
+        gpx.tracks.push(track(None, vec![points]));
+        assert!(filter_outliers(&mut gpx, &LIMITS, Outliers::Drop).is_empty());
+
+        // Just below the speed limit
+        let fast = OutlierLimits {
+            max_speed: 2.0,
+            ..LIMITS
+        };
+        let mut gpx = empty_gpx();
+        gpx.tracks.push(track(None, vec![walk(&north(3))]));
+        assert!(filter_outliers(&mut gpx, &fast, Outliers::Drop).is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+use gpx::{Gpx, Time, Waypoint};
+use time::format_description::well_known::Iso8601;
+use time::{Date, OffsetDateTime, Time as TimeOfDay};
+
+use crate::load::parse_time;
+use crate::point_count;
+
+/// The parts of a track file to use, for files that hold many trips.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Selection {
+    /// Tracks and routes by name or by number, counting the tracks and then
+    /// the routes from 1. All of them if empty.
+    pub tracks: Vec<String>,
+    /// Segments of every chosen track by number from 1. All if empty.
+    pub segments: Vec<usize>,
+    /// Only points recorded at or after this time.
+    pub from: Option<Time>,
+    /// Only points recorded at or before this time.
+    pub until: Option<Time>,
+}
+
+impl Selection {
+    /// Whether the whole file is used.
+    #[must_use]
+    pub const fn is_all(&self) -> bool {
+        self.tracks.is_empty()
+            && self.segments.is_empty()
+            && self.from.is_none()
+            && self.until.is_none()
+    }
+}
+
+/// Keeps the chosen tracks, routes, segments and points and drops the rest.
+/// Waypoints are kept, they are not part of a trip. With a time window,
+/// points without a time are dropped.
+///
+/// # Errors
+///
+/// Fails if a track is neither found by name nor by number, or if nothing
+/// is left.
+pub fn select(gpx: &mut Gpx, selection: &Selection) -> Result<(), String> {
+    if selection.is_all() {
+        return Ok(());
+    }
+    if !selection.tracks.is_empty() {
+        let names: Vec<Option<&str>> = gpx
+            .tracks
+            .iter()
+            .map(|t| t.name.as_deref())
+            .chain(gpx.routes.iter().map(|r| r.name.as_deref()))
+            .collect();
+        let mut keep = vec![false; names.len()];
+        for wanted in &selection.tracks {
+            let by_name: Vec<usize> = (0..names.len())
+                .filter(|&i| names[i] == Some(wanted.as_str()))
+                .collect();
+            let found = if by_name.is_empty() {
+                wanted
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| (1..=names.len()).contains(n))
+                    .map(|n| vec![n - 1])
+                    .unwrap_or_default()
+            } else {
+                by_name
+            };
+            if found.is_empty() {
+                return Err(format!(
+                    "no track or route '{wanted}', the file has {}",
+                    listing(&names)
+                ));
+            }
+            for i in found {
+                keep[i] = true;
+            }
+        }
+        let mut keep = keep.into_iter();
+        gpx.tracks.retain(|_| keep.next() == Some(true));
+        gpx.routes.retain(|_| keep.next() == Some(true));
+    }
+
+    if !selection.segments.is_empty() {
+        for track in &mut gpx.tracks {
+            let mut number = 0;
+            track.segments.retain(|_| {
+                number += 1;
+                selection.segments.contains(&number)
+            });
+        }
+    }
+
+    if selection.from.is_some() || selection.until.is_some() {
+        let from = selection.from.map(OffsetDateTime::from);
+        let until = selection.until.map(OffsetDateTime::from);
+        let within = |p: &Waypoint| {
+            p.time.map(OffsetDateTime::from).is_some_and(|t| {
+                from.is_none_or(|from| t >= from) && until.is_none_or(|until| t <= until)
+            })
+        };
+        for segment in gpx.tracks.iter_mut().flat_map(|t| &mut t.segments) {
+            segment.points.retain(within);
+        }
+        for route in &mut gpx.routes {
+            route.points.retain(within);
+        }
+    }
+
+    for track in &mut gpx.tracks {
+        track.segments.retain(|s| !s.points.is_empty());
+    }
+    gpx.tracks.retain(|t| !t.segments.is_empty());
+    gpx.routes.retain(|r| !r.points.is_empty());
+    if point_count(gpx) == 0 {
+        return Err("the selected tracks, segments and times hold no points".to_string());
+    }
+    Ok(())
+}
+
+/// `1 "Name", 2, 3 "Other"` for the error message.
+fn listing(names: &[Option<&str>]) -> String {
+    let list: Vec<String> = names
+        .iter()
+        .zip(1..)
+        .map(|(name, n)| name.map_or_else(|| n.to_string(), |name| format!("{n} \"{name}\"")))
+        .collect();
+    if list.is_empty() {
+        "no tracks or routes".to_string()
+    } else {
+        list.join(", ")
+    }
+}
+
+/// The start of a time window: an RFC 3339 time like
+/// `2024-05-01T08:00:00+02:00`, or a date for the start of that day in UTC.
+///
+/// # Errors
+///
+/// Fails if `s` is neither.
+pub fn parse_start(s: &str) -> Result<Time, String> {
+    parse_moment(s, TimeOfDay::MIDNIGHT)
+}
+
+/// The end of a time window: an RFC 3339 time, or a date for the end of that
+/// day in UTC.
+///
+/// # Errors
+///
+/// Fails if `s` is neither.
+pub fn parse_end(s: &str) -> Result<Time, String> {
+    parse_moment(s, TimeOfDay::MAX)
+}
+
+fn parse_moment(s: &str, time_of_day: TimeOfDay) -> Result<Time, String> {
+    parse_time(s)
+        .or_else(|| {
+            let date = Date::parse(s.trim(), &Iso8601::DATE).ok()?;
+            Some(date.with_time(time_of_day).assume_utc().into())
+        })
+        .ok_or_else(|| format!("'{s}' is not a time like 2024-05-01T08:00:00Z or a date"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::{empty_gpx, track};
+    use geo_types::Point;
+
+    /// Two named tracks of one point per segment at the given hours of
+    /// 2024-05-01, and an unnamed route.
+    fn season() -> Gpx {
+        let point = |hour: u8| {
+            let mut point = Waypoint::new(Point::new(8.0, 47.0 + f64::from(hour) / 100.0));
+            point.time = parse_time(&format!("2024-05-01T{hour:02}:00:00Z"));
+            point
+        };
+        let mut gpx = empty_gpx();
+        gpx.tracks.push(track(
+            Some("Monday".into()),
+            vec![vec![point(8)], vec![point(9), point(10)]],
+        ));
+        gpx.tracks
+            .push(track(Some("Tuesday".into()), vec![vec![point(11)]]));
+        let mut route = gpx::Route::new();
+        route.points.push(Waypoint::new(Point::new(9.0, 46.0)));
+        gpx.routes.push(route);
+        gpx
+    }
+
+    #[test]
+    fn tracks_by_name_or_number() {
+        let mut gpx = season();
+        let selection = Selection {
+            tracks: vec!["Tuesday".into(), "3".into()],
+            ..Selection::default()
+        };
+        select(&mut gpx, &selection).unwrap();
+        assert_eq!(gpx.tracks.len(), 1);
+        assert_eq!(gpx.tracks[0].name.as_deref(), Some("Tuesday"));
+        assert_eq!(gpx.routes.len(), 1);
+
+        let unknown = Selection {
+            tracks: vec!["Sunday".into()],
+            ..Selection::default()
+        };
+        let error = select(&mut season(), &unknown).unwrap_err();
+        assert!(error.ends_with("the file has 1 \"Monday\", 2 \"Tuesday\", 3"));
+    }
+
+    #[test]
+    fn segments_and_time_window() {
+        let mut gpx = season();
+        let selection = Selection {
+            segments: vec![2],
+            ..Selection::default()
+        };
+        select(&mut gpx, &selection).unwrap();
+        // Tuesday has no second segment, the route is kept
+        assert_eq!(gpx.tracks.len(), 1);
+        assert_eq!(gpx.tracks[0].segments[0].points.len(), 2);
+        assert_eq!(gpx.routes.len(), 1);
+
+        let mut gpx = season();
+        let selection = Selection {
+            from: parse_start("2024-05-01T09:30:00Z").ok(),
+            until: parse_end("2024-05-01").ok(),
+            ..Selection::default()
+        };
+        select(&mut gpx, &selection).unwrap();
+        assert_eq!(point_count(&gpx), 2);
+        assert!(gpx.routes.is_empty());
+
+        let selection = Selection {
+            until: parse_end("2024-04-30").ok(),
+            ..Selection::default()
+        };
+        assert!(select(&mut season(), &selection).is_err());
+        assert!(parse_start("yesterday").is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::str::FromStr;
+
+use geo_types::Point;
+use gpx::Waypoint;
+use time::OffsetDateTime;
+
+use crate::EARTH_RADIUS;
+
+/// How a track is reduced to fewer points.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Simplify {
+    /// Keep every point.
+    Off,
+    /// Douglas–Peucker: drop points closer than the tolerance to the line
+    /// through the points kept.
+    #[default]
+    DouglasPeucker,
+    /// Visvalingam–Whyatt: drop points whose triangle with their neighbours
+    /// is smaller than the tolerance squared.
+    Visvalingam,
+    /// One point every tolerance meters along the track.
+    Resample,
+}
+
+impl FromStr for Simplify {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "douglas-peucker" => Ok(Self::DouglasPeucker),
+            "visvalingam" => Ok(Self::Visvalingam),
+            "resample" => Ok(Self::Resample),
+            _ => Err(format!(
+                "unknown simplification '{s}', use off, douglas-peucker, visvalingam or resample"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Simplify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::DouglasPeucker => write!(f, "douglas-peucker"),
+            Self::Visvalingam => write!(f, "visvalingam"),
+            Self::Resample => write!(f, "resample"),
+        }
+    }
+}
+
+/// Reduces a line of track points with `method`, `tolerance` in meters.
+///
+/// The first and the last point are always kept. Points added by resampling
+/// have their elevation and time interpolated.
+#[must_use]
+pub fn simplify(points: &[Waypoint], method: Simplify, tolerance: f64) -> Vec<Waypoint> {
+    if points.len() <= 2 || tolerance <= 0.0 {
+        return points.to_vec();
+    }
+    let xy = project(points);
+    let keep = match method {
+        Simplify::Off => return points.to_vec(),
+        Simplify::DouglasPeucker => douglas_peucker(&xy, tolerance),
+        Simplify::Visvalingam => visvalingam(&xy, tolerance * tolerance),
+        Simplify::Resample => return resample(points, &xy, tolerance),
+    };
+    points
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(p, _)| p.clone())
+        .collect()
+}
+
+/// Positions in meters on a plane touching the earth at the first point,
+/// close enough over the extent of a track.
+fn project(points: &[Waypoint]) -> Vec<(f64, f64)> {
+    let cos = points[0].point().y().to_radians().cos();
+    points
+        .iter()
+        .map(|p| {
+            let p = p.point();
+            (
+                p.x().to_radians() * cos * EARTH_RADIUS,
+                p.y().to_radians() * EARTH_RADIUS,
+            )
+        })
+        .collect()
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+/// Distance of `p` to the segment from `a` to `b`.
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx.mul_add(dx, dy * dy);
+    let t = if len2 > 0.0 {
+        ((p.0 - a.0).mul_add(dx, (p.1 - a.1) * dy) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    distance(p, (t.mul_add(dx, a.0), t.mul_add(dy, a.1)))
+}
+
+fn triangle_area(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    (b.0 - a.0)
+        .mul_add(c.1 - a.1, -(b.1 - a.1) * (c.0 - a.0))
+        .abs()
+        / 2.0
+}
+
+fn douglas_peucker(xy: &[(f64, f64)], tolerance: f64) -> Vec<bool> {
+    let last = xy.len() - 1;
+    let mut keep = vec![false; xy.len()];
+    keep[0] = true;
+    keep[last] = true;
+    let mut ranges = vec![(0, last)];
+    while let Some((start, end)) = ranges.pop() {
+        let farthest = (start + 1..end)
+            .map(|i| (i, segment_distance(xy[i], xy[start], xy[end])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, d)) = farthest {
+            if d > tolerance {
+                keep[i] = true;
+                ranges.push((start, i));
+                ranges.push((i, end));
+            }
+        }
+    }
+    keep
+}
+
+fn visvalingam(xy: &[(f64, f64)], min_area: f64) -> Vec<bool> {
+    let n = xy.len();
+    let mut prev: Vec<usize> = (0..n).map(|i| i.saturating_sub(1)).collect();
+    let mut next: Vec<usize> = (1..=n).collect();
+    let mut area = vec![f64::INFINITY; n];
+    // Areas are never negative, so their bits sort like the numbers
+    let mut smallest = BinaryHeap::new();
+    for i in 1..n - 1 {
+        area[i] = triangle_area(xy[i - 1], xy[i], xy[i + 1]);
+        smallest.push(Reverse((area[i].to_bits(), i)));
+    }
+
+    let mut keep = vec![true; n];
+    while let Some(Reverse((bits, i))) = smallest.pop() {
+        if !keep[i] || bits != area[i].to_bits() {
+            // Removed or its area changed since
+            continue;
+        }
+        let removed = area[i];
+        if removed >= min_area {
+            break;
+        }
+        keep[i] = false;
+        let (p, q) = (prev[i], next[i]);
+        next[p] = q;
+        prev[q] = p;
+        for j in [p, q] {
+            if j != 0 && j != n - 1 {
+                // A neighbour never gets less important than the point
+                // removed before it
+                area[j] = triangle_area(xy[prev[j]], xy[j], xy[next[j]]).max(removed);
+                smallest.push(Reverse((area[j].to_bits(), j)));
+            }
+        }
+    }
+    keep
+}
+
+fn resample(points: &[Waypoint], xy: &[(f64, f64)], spacing: f64) -> Vec<Waypoint> {
+    let mut resampled = vec![points[0].clone()];
+    // Distance walked since the last point added
+    let mut walked = 0.0;
+    for i in 1..points.len() {
+        let length = distance(xy[i - 1], xy[i]);
+        // Distance into this segment of the next point to add
+        let mut at = spacing - walked;
+        loop {
+            if at > length {
+                break;
+            }
+            resampled.push(interpolate(&points[i - 1], &points[i], at / length));
+            at += spacing;
+        }
+        walked = length - (at - spacing);
+    }
+    if walked > 0.0 {
+        resampled.push(points[points.len() - 1].clone());
+    }
+    resampled
+}
+
+fn interpolate(a: &Waypoint, b: &Waypoint, t: f64) -> Waypoint {
+    let (pa, pb) = (a.point(), b.point());
+    let mut p = Waypoint::new(Point::new(
+        t.mul_add(pb.x() - pa.x(), pa.x()),
+        t.mul_add(pb.y() - pa.y(), pa.y()),
+    ));
+    if let (Some(ea), Some(eb)) = (a.elevation, b.elevation) {
+        p.elevation = Some(t.mul_add(eb - ea, ea));
+    }
+    if let (Some(ta), Some(tb)) = (a.time, b.time) {
+        let (ta, tb) = (OffsetDateTime::from(ta), OffsetDateTime::from(tb));
+        p.time = Some((ta + (tb - ta) * t).into());
+    }
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points along the equator, `x` in meters east and `y` in meters north.
+    fn line(xy: &[(f64, f64)]) -> Vec<Waypoint> {
+        let degrees = |m: f64| (m / EARTH_RADIUS).to_degrees();
+        xy.iter()
+            .map(|(x, y)| Waypoint::new(Point::new(degrees(*x), degrees(*y))))
+            .collect()
+    }
+
+    fn xs(points: &[Waypoint]) -> Vec<f64> {
+        project(points).iter().map(|p| p.0.round()).collect()
+    }
+
+    #[test]
+    fn douglas_peucker_keeps_corners() {
+        let points = line(&[(0.0, 0.0), (50.0, 1.0), (100.0, 0.0), (100.0, 100.0)]);
+        let simple = simplify(&points, Simplify::DouglasPeucker, 5.0);
+        assert_eq!(xs(&simple), vec![0.0, 100.0, 100.0]);
+        let exact = simplify(&points, Simplify::DouglasPeucker, 0.5);
+        assert_eq!(exact.len(), 4);
+    }
+
+    #[test]
+    fn visvalingam_drops_small_triangles() {
+        let points = line(&[
+            (0.0, 0.0),
+            (10.0, 1.0),
+            (20.0, 0.0),
+            (30.0, 40.0),
+            (40.0, 0.0),
+        ]);
+        let simple = simplify(&points, Simplify::Visvalingam, 5.0);
+        assert_eq!(xs(&simple), vec![0.0, 20.0, 30.0, 40.0]);
+    }
+
+    #[test]
+    fn resample_spaces_points_evenly() {
+        let mut points = line(&[(0.0, 0.0), (25.0, 0.0), (100.0, 0.0), (105.0, 0.0)]);
+        points[0].elevation = Some(100.0);
+        points[2].elevation = Some(200.0);
+        points[1].elevation = Some(125.0);
+        let simple = simplify(&points, Simplify::Resample, 30.0);
+        assert_eq!(xs(&simple), vec![0.0, 30.0, 60.0, 90.0, 105.0]);
+        assert!((simple[2].elevation.unwrap() - 160.0).abs() < 1e-6);
+        assert_eq!(simple[4].elevation, None);
+    }
+}
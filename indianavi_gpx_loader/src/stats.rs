@@ -0,0 +1,244 @@
+use std::time::Duration;
+
+use geo_types::Point;
+use gpx::{Gpx, Waypoint};
+
+use crate::EARTH_RADIUS;
+
+/// Great circle distance in meters.
+#[must_use]
+pub fn haversine(a: Point, b: Point) -> f64 {
+    let (lat_a, lat_b) = (a.y().to_radians(), b.y().to_radians());
+    let half_lat = (lat_b - lat_a) / 2.0;
+    let half_lon = (b.x() - a.x()).to_radians() / 2.0;
+    let h = (lat_a.cos() * lat_b.cos()).mul_add(half_lon.sin().powi(2), half_lat.sin().powi(2));
+    2.0 * EARTH_RADIUS * h.sqrt().asin()
+}
+
+/// Length, climb and walking time of a line of track points.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Meters along the ground.
+    pub distance: f64,
+    /// Meters climbed, without changes below the smoothing.
+    pub ascent: f64,
+    /// Meters descended, without changes below the smoothing.
+    pub descent: f64,
+    pub min_elevation: Option<f64>,
+    pub max_elevation: Option<f64>,
+    /// Naismith's rule: an hour per 5 km and per 600 m of ascent.
+    pub naismith: Duration,
+    /// Tobler's hiking function over every leg between two points.
+    pub tobler: Duration,
+}
+
+impl Stats {
+    /// Numbers of one line of points. Elevation changes are only counted once
+    /// they exceed `smoothing` meters, which hides GPS and barometer noise.
+    #[must_use]
+    pub fn of(points: &[Waypoint], smoothing: f64) -> Self {
+        let mut stats = Self::default();
+        let mut tobler_hours = 0.0;
+        for leg in points.windows(2) {
+            let distance = haversine(leg[0].point(), leg[1].point());
+            if distance <= 0.0 {
+                continue;
+            }
+            let slope = match (leg[0].elevation, leg[1].elevation) {
+                (Some(from), Some(to)) => (to - from) / distance,
+                _ => 0.0,
+            };
+            let speed = 6.0 * (-3.5 * (slope + 0.05).abs()).exp();
+            tobler_hours += distance / 1000.0 / speed;
+            stats.distance += distance;
+        }
+
+        let mut reference = None;
+        for elevation in points.iter().filter_map(|p| p.elevation) {
+            stats.min_elevation = Some(stats.min_elevation.map_or(elevation, |m| m.min(elevation)));
+            stats.max_elevation = Some(stats.max_elevation.map_or(elevation, |m| m.max(elevation)));
+            let Some(from) = reference else {
+                reference = Some(elevation);
+                continue;
+            };
+            let change = elevation - from;
+            if change.abs() > smoothing {
+                if change > 0.0 {
+                    stats.ascent += change;
+                } else {
+                    stats.descent -= change;
+                }
+                reference = Some(elevation);
+            }
+        }
+
+        let naismith_hours = stats.distance / 5000.0 + stats.ascent / 600.0;
+        stats.naismith = Duration::from_secs_f64(naismith_hours * 3600.0);
+        stats.tobler = Duration::from_secs_f64(tobler_hours * 3600.0);
+        stats
+    }
+
+    /// Adds the numbers of the following part of a tour.
+    pub fn merge(&mut self, other: &Self) {
+        self.distance += other.distance;
+        self.ascent += other.ascent;
+        self.descent += other.descent;
+        self.min_elevation = match (self.min_elevation, other.min_elevation) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.max_elevation = match (self.max_elevation, other.max_elevation) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        self.naismith += other.naismith;
+        self.tobler += other.tobler;
+    }
+}
+
+/// Numbers of one track segment or route.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentStats {
+    /// Track or route name, numbered when there is none, with the segment
+    /// number for tracks of several segments.
+    pub name: String,
+    pub stats: Stats,
+}
+
+/// Numbers of every track segment and route in file order.
+#[must_use]
+pub fn segment_stats(gpx: &Gpx, smoothing: f64) -> Vec<SegmentStats> {
+    let tracks = gpx.tracks.iter().flat_map(|t| &t.segments);
+    let lines = tracks
+        .map(|s| &s.points)
+        .chain(gpx.routes.iter().map(|r| &r.points));
+    lines
+        .zip(line_names(gpx))
+        .map(|(points, name)| SegmentStats {
+            name,
+            stats: Stats::of(points, smoothing),
+        })
+        .collect()
+}
+
+/// Names of every track segment and route in file order: the name of the
+/// track or route or its number, and the number of the segment when a track
+/// has several.
+pub fn line_names(gpx: &Gpx) -> Vec<String> {
+    let mut names = Vec::new();
+    for (i, track) in gpx.tracks.iter().enumerate() {
+        let name = track
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("Track {}", i + 1));
+        for j in 0..track.segments.len() {
+            names.push(if track.segments.len() > 1 {
+                format!("{name} #{}", j + 1)
+            } else {
+                name.clone()
+            });
+        }
+    }
+    for (i, route) in gpx.routes.iter().enumerate() {
+        names.push(
+            route
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("Route {}", i + 1)),
+        );
+    }
+    names
+}
+
+/// (meters from the start, elevation) of every point with an elevation,
+/// following all tracks and then all routes, the gaps between them included.
+#[must_use]
+pub fn elevation_profile(gpx: &Gpx) -> Vec<(f64, f64)> {
+    let tracks = gpx.tracks.iter().flat_map(|t| &t.segments);
+    let lines = tracks
+        .map(|s| &s.points)
+        .chain(gpx.routes.iter().map(|r| &r.points));
+    let mut profile = Vec::new();
+    let mut distance = 0.0;
+    let mut previous = None;
+    for p in lines.flatten() {
+        if let Some(previous) = previous {
+            distance += haversine(previous, p.point());
+        }
+        previous = Some(p.point());
+        if let Some(elevation) = p.elevation {
+            profile.push((distance, elevation));
+        }
+    }
+    profile
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat: f64, ele: f64) -> Waypoint {
+        let mut p = Waypoint::new(Point::new(8.0, lat));
+        p.elevation = Some(ele);
+        p
+    }
+
+    #[test]
+    fn haversine_of_one_degree_latitude() {
+        let d = haversine(Point::new(8.0, 47.0), Point::new(8.0, 48.0));
+        assert!((d - 111_195.0).abs() < 1.0, "{d}");
+    }
+
+    #[test]
+    fn smoothing_hides_noise() {
+        let points: Vec<Waypoint> = [100.0, 102.0, 99.0, 101.0, 120.0, 118.0, 110.0]
+            .iter()
+            .zip([47.0, 47.001, 47.002, 47.003, 47.004, 47.005, 47.006])
+            .map(|(ele, lat)| point(lat, *ele))
+            .collect();
+        let stats = Stats::of(&points, 5.0);
+        assert!((stats.ascent - 20.0).abs() < 1e-9);
+        assert!((stats.descent - 10.0).abs() < 1e-9);
+        assert_eq!(stats.min_elevation, Some(99.0));
+        assert_eq!(stats.max_elevation, Some(120.0));
+        assert!((Stats::of(&points, 0.0).ascent - 23.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn walking_times_on_the_flat() {
+        // 5 km due north
+        let points = [point(47.0, 500.0), point(47.0 + 5000.0 / 111_195.0, 500.0)];
+        let stats = Stats::of(&points, 5.0);
+        assert!((stats.naismith.as_secs_f64() - 3600.0).abs() < 2.0);
+        // Tobler walks 5.04 km/h on flat ground
+        let tobler = 5.0 / (6.0 * (-0.175_f64).exp()) * 3600.0;
+        assert!((stats.tobler.as_secs_f64() - tobler).abs() < 2.0);
+    }
+
+    #[test]
+    fn segments_are_named_and_merged() {
+        let gpx = gpx::read(crate::tests::TRACK_AND_ROUTE.as_bytes()).unwrap();
+        let segments = segment_stats(&gpx, 5.0);
+        let names: Vec<&str> = segments.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Tour #1", "Tour #2", "Back"]);
+
+        let mut total = Stats::default();
+        for s in &segments {
+            total.merge(&s.stats);
+        }
+        assert_eq!(total.min_elevation, Some(400.0));
+        assert_eq!(total.max_elevation, Some(420.0));
+        assert!(total.distance > segments[0].stats.distance);
+    }
+
+    #[test]
+    fn profile_runs_through_all_lines() {
+        let gpx = gpx::read(crate::tests::TRACK_AND_ROUTE.as_bytes()).unwrap();
+        let profile = elevation_profile(&gpx);
+        // The route has no elevation
+        let elevations: Vec<f64> = profile.iter().map(|p| p.1).collect();
+        assert_eq!(elevations, vec![400.0, 410.0, 420.0]);
+        assert!(profile[0].0.abs() < 1e-9);
+        assert!(profile.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+}
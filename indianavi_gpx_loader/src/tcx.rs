@@ -0,0 +1,106 @@
+use gpx::Gpx;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::load::{empty_gpx, parse_time, track, PointBuilder};
+use crate::{LoadError, TrackFormat};
+
+/// Reads the activities and courses of a TCX file as tracks, one segment
+/// per `<Track>`, and the course points as waypoints.
+pub fn read(data: &[u8]) -> Result<Gpx, LoadError> {
+    let mut gpx = empty_gpx();
+    let mut path: Vec<String> = Vec::new();
+    let mut text = String::new();
+    let mut point: Option<PointBuilder> = None;
+    for event in EventReader::new(data) {
+        match event.map_err(|e| LoadError::xml(TrackFormat::Tcx, &e))? {
+            XmlEvent::StartElement { name, .. } => {
+                match name.local_name.as_str() {
+                    "Activity" | "Course" => gpx.tracks.push(track(None, Vec::new())),
+                    "Track" => {
+                        if gpx.tracks.is_empty() {
+                            gpx.tracks.push(track(None, Vec::new()));
+                        }
+                        let segments = &mut gpx.tracks.last_mut().expect("a track").segments;
+                        segments.push(gpx::TrackSegment::new());
+                    }
+                    "Trackpoint" | "CoursePoint" => point = Some(PointBuilder::default()),
+                    _ => {}
+                }
+                path.push(name.local_name);
+                text.clear();
+            }
+            XmlEvent::Characters(s) => text.push_str(&s),
+            XmlEvent::EndElement { name } => {
+                path.pop();
+                let parent = path.last().map(String::as_str);
+                match (name.local_name.as_str(), parent, point.as_mut()) {
+                    ("LatitudeDegrees", _, Some(p)) => p.lat = text.trim().parse().ok(),
+                    ("LongitudeDegrees", _, Some(p)) => p.lon = text.trim().parse().ok(),
+                    ("AltitudeMeters", _, Some(p)) => p.elevation = text.trim().parse().ok(),
+                    ("Time", _, Some(p)) => p.time = parse_time(&text),
+                    ("Name", Some("CoursePoint"), Some(p)) => p.name = Some(text.trim().into()),
+                    ("Name", Some("Course"), _) | ("Id", Some("Activity"), _) => {
+                        if let Some(track) = gpx.tracks.last_mut() {
+                            track.name = Some(text.trim().to_string());
+                        }
+                    }
+                    ("Trackpoint", ..) => {
+                        let segment = gpx.tracks.last_mut().and_then(|t| t.segments.last_mut());
+                        if let (Some(segment), Some(p)) =
+                            (segment, point.take().and_then(PointBuilder::build))
+                        {
+                            segment.points.push(p);
+                        }
+                    }
+                    ("CoursePoint", ..) => {
+                        if let Some(p) = point.take().and_then(PointBuilder::build) {
+                            gpx.waypoints.push(p);
+                        }
+                    }
+                    _ => {}
+                }
+                text.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(gpx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COURSE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Courses><Course>
+    <Name>Lake loop</Name>
+    <Track>
+      <Trackpoint><Time>2024-05-01T10:00:00Z</Time>
+        <Position><LatitudeDegrees>47.1</LatitudeDegrees><LongitudeDegrees>8.1</LongitudeDegrees></Position>
+        <AltitudeMeters>430.5</AltitudeMeters></Trackpoint>
+      <Trackpoint><Time>2024-05-01T10:00:05Z</Time></Trackpoint>
+      <Trackpoint>
+        <Position><LatitudeDegrees>47.2</LatitudeDegrees><LongitudeDegrees>8.2</LongitudeDegrees></Position>
+      </Trackpoint>
+    </Track>
+    <CoursePoint><Name>Water</Name>
+      <Position><LatitudeDegrees>47.15</LatitudeDegrees><LongitudeDegrees>8.15</LongitudeDegrees></Position>
+    </CoursePoint>
+  </Course></Courses>
+</TrainingCenterDatabase>"#;
+
+    #[test]
+    fn course_becomes_track_and_waypoints() {
+        let gpx = read(COURSE.as_bytes()).unwrap();
+        assert_eq!(gpx.tracks.len(), 1);
+        assert_eq!(gpx.tracks[0].name.as_deref(), Some("Lake loop"));
+        let points = &gpx.tracks[0].segments[0].points;
+        // The point without a position is dropped
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].elevation, Some(430.5));
+        assert!(points[0].time.is_some());
+        assert!((points[1].point().y() - 47.2).abs() < 1e-9);
+        assert_eq!(gpx.waypoints[0].name.as_deref(), Some("Water"));
+    }
+}
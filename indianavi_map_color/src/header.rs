@@ -0,0 +1,324 @@
+use std::borrow::Cow;
+
+use crate::{rle_decode, BitOrder, PanelProfile, PixelPacking};
+
+/// First bytes of a tile file that has a header.
+pub const MAGIC: [u8; 4] = *b"INMT";
+/// Format version written by this crate. Readers reject newer versions.
+pub const HEADER_VERSION: u8 = 1;
+/// Size of the header on disk.
+pub const HEADER_LEN: usize = 28;
+
+const FLAG_ROW_PADDING: u8 = 1;
+const FLAG_LSB_FIRST: u8 = 1 << 1;
+const FLAG_RLE: u8 = 1 << 2;
+const KNOWN_FLAGS: u8 = FLAG_ROW_PADDING | FLAG_LSB_FIRST | FLAG_RLE;
+
+/// Describes the pixel data that follows it in a tile file.
+///
+/// All fields are little endian:
+///
+/// | offset | size | field                               |
+/// |--------|------|-------------------------------------|
+/// | 0      | 4    | magic `INMT`                        |
+/// | 4      | 1    | format version                      |
+/// | 5      | 1    | flags, see below                    |
+/// | 6      | 1    | bits per pixel                      |
+/// | 7      | 1    | palette id                          |
+/// | 8      | 2    | width                               |
+/// | 10     | 2    | height                              |
+/// | 12     | 1    | zoom                                |
+/// | 13     | 3    | reserved, zero                      |
+/// | 16     | 4    | tile x                              |
+/// | 20     | 4    | tile y                              |
+/// | 24     | 4    | CRC32 of the pixel data as stored   |
+///
+/// Flags: bit 0 rows are padded, bit 1 the first pixel is in the least
+/// significant bits, bit 2 the pixel data is run-length encoded (see
+/// `rle_encode`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileHeader {
+    pub version: u8,
+    pub packing: PixelPacking,
+    pub palette_id: u8,
+    pub width: u16,
+    pub height: u16,
+    pub zoom: u8,
+    pub x: u32,
+    pub y: u32,
+    /// The pixel data is run-length encoded.
+    pub compressed: bool,
+    pub crc32: u32,
+}
+
+impl TileHeader {
+    /// Header for uncompressed `data`, packed for `panel` with `packing`.
+    /// Set `compressed` and `crc32` for run-length encoded data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tile is larger than 65535 pixels on a side or the zoom
+    /// is above 255.
+    #[must_use]
+    pub fn new(
+        panel: &PanelProfile,
+        packing: &PixelPacking,
+        (width, height): (u32, u32),
+        (zoom, x, y): (u32, u32, u32),
+        data: &[u8],
+    ) -> Self {
+        Self {
+            version: HEADER_VERSION,
+            packing: *packing,
+            palette_id: panel.id,
+            width: u16::try_from(width).expect("tile width to fit the header"),
+            height: u16::try_from(height).expect("tile height to fit the header"),
+            zoom: u8::try_from(zoom).expect("zoom to fit the header"),
+            x,
+            y,
+            compressed: false,
+            crc32: crc32(data),
+        }
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut flags = 0;
+        if self.packing.row_padding {
+            flags |= FLAG_ROW_PADDING;
+        }
+        if self.packing.order == BitOrder::LsbFirst {
+            flags |= FLAG_LSB_FIRST;
+        }
+        if self.compressed {
+            flags |= FLAG_RLE;
+        }
+
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = self.version;
+        bytes[5] = flags;
+        bytes[6] = self.packing.bits_per_pixel;
+        bytes[7] = self.palette_id;
+        bytes[8..10].copy_from_slice(&self.width.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.height.to_le_bytes());
+        bytes[12] = self.zoom;
+        bytes[16..20].copy_from_slice(&self.x.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.y.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.crc32.to_le_bytes());
+        bytes
+    }
+
+    /// Splits a tile file into its header, if it has one, and the pixel data.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file starts with the magic but the header is truncated,
+    /// from a newer format version or uses flags this version does not know.
+    pub fn split(file: &[u8]) -> Result<(Option<Self>, &[u8]), String> {
+        if !file.starts_with(&MAGIC) {
+            return Ok((None, file));
+        }
+        if file.len() < HEADER_LEN {
+            return Err(format!("header is truncated at {} bytes", file.len()));
+        }
+
+        let version = file[4];
+        if version > HEADER_VERSION {
+            return Err(format!(
+                "format version {version} is newer than this tool ({HEADER_VERSION})"
+            ));
+        }
+        let flags = file[5];
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(format!("unknown header flags {flags:#04x}"));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([file[i], file[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_le_bytes([file[i], file[i + 1], file[i + 2], file[i + 3]]);
+
+        let header = Self {
+            version,
+            packing: PixelPacking {
+                bits_per_pixel: file[6],
+                order: if flags & FLAG_LSB_FIRST == 0 {
+                    BitOrder::MsbFirst
+                } else {
+                    BitOrder::LsbFirst
+                },
+                row_padding: flags & FLAG_ROW_PADDING != 0,
+            },
+            palette_id: file[7],
+            width: u16_at(8),
+            height: u16_at(10),
+            zoom: file[12],
+            x: u32_at(16),
+            y: u32_at(20),
+            compressed: flags & FLAG_RLE != 0,
+            crc32: u32_at(24),
+        };
+        Ok((Some(header), &file[HEADER_LEN..]))
+    }
+
+    /// The panel named by the palette id.
+    ///
+    /// # Errors
+    ///
+    /// Fails for palette ids no panel profile uses.
+    pub fn panel(&self) -> Result<PanelProfile, String> {
+        PanelProfile::from_id(self.palette_id)
+            .ok_or_else(|| format!("unknown palette id {}", self.palette_id))
+    }
+
+    /// Checks the pixel data against size, palette and checksum of the header.
+    ///
+    /// # Errors
+    ///
+    /// Describes the first mismatch.
+    pub fn verify(&self, data: &[u8]) -> Result<(), String> {
+        self.pixels(data).map(|_| ())
+    }
+
+    /// Checks the stored pixel data and returns it decompressed, as packed
+    /// pixels.
+    ///
+    /// # Errors
+    ///
+    /// Describes the first mismatch between header and data.
+    pub fn pixels<'a>(&self, data: &'a [u8]) -> Result<Cow<'a, [u8]>, String> {
+        self.packing.validate(&self.panel()?)?;
+        let crc = crc32(data);
+        if crc != self.crc32 {
+            return Err(format!(
+                "CRC32 is {crc:08x}, the header says {:08x}",
+                self.crc32
+            ));
+        }
+        let expected = self
+            .packing
+            .packed_len(u32::from(self.width), u32::from(self.height));
+        let pixels = if self.compressed {
+            Cow::Owned(rle_decode(data, expected)?)
+        } else {
+            Cow::Borrowed(data)
+        };
+        if pixels.len() != expected {
+            return Err(format!(
+                "{} bytes of pixel data, the header describes {expected}",
+                pixels.len()
+            ));
+        }
+        Ok(pixels)
+    }
+}
+
+/// CRC-32 as used by zlib and PNG (polynomial 0xEDB88320, reflected).
+#[must_use]
+pub fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0_u32;
+        while i < 256 {
+            let mut crc = i;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 0 {
+                    crc >> 1
+                } else {
+                    (crc >> 1) ^ 0xEDB8_8320
+                };
+                bit += 1;
+            }
+            table[i as usize] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    !data.iter().fold(u32::MAX, |crc, byte| {
+        TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rle_encode;
+
+    fn header(data: &[u8]) -> TileHeader {
+        let packing = PixelPacking {
+            bits_per_pixel: 2,
+            order: BitOrder::LsbFirst,
+            row_padding: true,
+        };
+        TileHeader::new(
+            &PanelProfile::BWR,
+            &packing,
+            (3, 2),
+            (16, 34_567, 22_222),
+            data,
+        )
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let data = [0b0001_1001, 0b0000_0110];
+        let header = header(&data);
+        let mut file = header.to_bytes().to_vec();
+        file.extend_from_slice(&data);
+
+        let (parsed, pixels) = TileHeader::split(&file).unwrap();
+        assert_eq!(parsed, Some(header));
+        assert_eq!(pixels, data);
+        header.verify(pixels).unwrap();
+    }
+
+    #[test]
+    fn compressed_data_is_checked_and_decoded() {
+        let data = [0x12; 6];
+        let stored = rle_encode(&data);
+        let mut header = TileHeader::new(
+            &PanelProfile::ACEP_7,
+            &PixelPacking::default(),
+            (4, 3),
+            (14, 1, 2),
+            &stored,
+        );
+        header.compressed = true;
+
+        let (parsed, payload) = TileHeader::split(&[&header.to_bytes()[..], &stored].concat())
+            .map(|(h, p)| (h, p.to_vec()))
+            .unwrap();
+        assert_eq!(parsed, Some(header));
+        assert_eq!(header.pixels(&payload).unwrap().as_ref(), data);
+        assert!(header.pixels(&data).is_err());
+    }
+
+    #[test]
+    fn bare_data_has_no_header() {
+        let data = [0x43; 8];
+        assert_eq!(TileHeader::split(&data), Ok((None, &data[..])));
+    }
+
+    #[test]
+    fn rejects_corruption_and_newer_versions() {
+        let data = [0b0001_1001, 0b0000_0110];
+        let header = header(&data);
+        assert!(header.verify(&[0b0001_1001, 0b0000_0111]).is_err());
+        assert!(header.verify(&data[..1]).is_err());
+
+        let mut bytes = header.to_bytes();
+        bytes[4] = HEADER_VERSION + 1;
+        assert!(TileHeader::split(&bytes).is_err());
+        bytes[4] = HEADER_VERSION;
+        bytes[5] = 0x80;
+        assert!(TileHeader::split(&bytes).is_err());
+        assert!(TileHeader::split(&bytes[..10]).is_err());
+    }
+}
@@ -5,11 +5,31 @@
 #![warn(clippy::all, clippy::nursery, clippy::pedantic)]
 #![allow(clippy::non_ascii_literal)]
 
-use colored::*;
-use image::io::Reader as ImageReader;
-use image::{GenericImageView, ImageError, Rgb, RgbImage};
+use image::{
+    DynamicImage, GenericImageView, ImageBuffer, ImageError, ImageFormat, ImageReader, Pixel, Rgb,
+    RgbImage, Rgba,
+};
 use lab::Lab;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
 use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+mod header;
+mod packing;
+mod panel;
+mod resample;
+mod rle;
+
+pub use header::{crc32, TileHeader, HEADER_LEN, HEADER_VERSION, MAGIC};
+pub use packing::{BitOrder, PixelPacking};
+pub use panel::PanelProfile;
+pub use resample::{
+    fit_tile, merge_tiles, overzoom_tile, split_tile, ResampleFilter, UpscaleFilter,
+};
+pub use rle::{rle_decode, rle_encode};
 
 const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
 const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
@@ -18,122 +38,444 @@ const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
 const GREEN: Rgb<u8> = Rgb([0, 255, 0]);
 const YELLOW: Rgb<u8> = Rgb([255, 255, 50]);
 const ORANGE: Rgb<u8> = Rgb([255, 127, 0]);
+const DARK_GRAY: Rgb<u8> = Rgb([85, 85, 85]);
+const LIGHT_GRAY: Rgb<u8> = Rgb([170, 170, 170]);
+/// Shown for raw values that are no palette color.
+const INVALID: Rgb<u8> = Rgb([255, 0, 255]);
 
+/// Width and height of a device tile in pixels.
+pub const TILE_SIZE: u32 = 256;
+
+/// Raw value of a color on the 7-color panel, 7 for anything else.
+#[must_use]
 pub fn color_to_raw(c: Rgb<u8>) -> u8 {
-    match c {
-        BLACK => 0,
-        WHITE => 1,
-        RED => 4,
-        BLUE => 3,
-        GREEN => 2,
-        YELLOW => 5,
-        ORANGE => 6,
-        _ => 7,
+    PanelProfile::ACEP_7.raw_value(c).unwrap_or(7)
+}
+
+/// Inverse of `color_to_raw`.
+#[must_use]
+pub fn raw_to_color(raw: u8) -> Option<Rgb<u8>> {
+    PanelProfile::ACEP_7.color(raw)
+}
+
+/// Unpacks raw tile data written by `convert_image` into an image.
+///
+/// # Errors
+///
+/// Fails if `raw` does not hold exactly `width * height` pixels.
+pub fn raw_to_image(
+    raw: &[u8],
+    width: u32,
+    height: u32,
+    panel: &PanelProfile,
+    packing: &PixelPacking,
+) -> Result<RgbImage, String> {
+    let values = packing.unpack(raw, width, height)?;
+    let mut img = RgbImage::new(width, height);
+    for (pixel, value) in img.pixels_mut().zip(values) {
+        *pixel = panel.color(value).unwrap_or(INVALID);
     }
+    Ok(img)
 }
 
-fn full_color(_: u32, _: u32, c: Vec<Rgb<u8>>) -> Rgb<u8> {
-    c[0]
+/// Decodes a tile file as written by the converter. Files with a header are
+/// checked and decoded as the header describes, bare pixel data as a
+/// `TILE_SIZE` square with the given panel and packing.
+///
+/// # Errors
+///
+/// Fails if the header is invalid or does not match the pixel data.
+pub fn tile_to_image(
+    file: &[u8],
+    panel: &PanelProfile,
+    packing: &PixelPacking,
+) -> Result<RgbImage, String> {
+    match TileHeader::split(file)? {
+        (Some(header), data) => {
+            let pixels = header.pixels(data)?;
+            raw_to_image(
+                &pixels,
+                u32::from(header.width),
+                u32::from(header.height),
+                &header.panel()?,
+                &header.packing,
+            )
+        }
+        (None, data) => raw_to_image(data, TILE_SIZE, TILE_SIZE, panel, packing),
+    }
 }
 
-fn fiddyfiddy(x: u32, y: u32, c: Vec<Rgb<u8>>) -> Rgb<u8> {
-    if (x % 2) == 1 {
-        if (y % 2) == 1 {
-            return c[0];
-        } else {
-            return c[1];
+/// Draws a line of `width` pixels between two points, without anti-aliasing.
+/// Parts outside the image are clipped.
+pub fn draw_line<P: Pixel>(
+    img: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    from: (f64, f64),
+    to: (f64, f64),
+    width: u32,
+    color: P,
+) {
+    let reach = width.max(1) - 1;
+    let offset = f64::from(reach / 2);
+    let (max_x, max_y) = (f64::from(img.width()), f64::from(img.height()));
+    let margin = f64::from(reach) + 1.0;
+    if (from.0 < -margin && to.0 < -margin)
+        || (from.1 < -margin && to.1 < -margin)
+        || (from.0 > max_x + margin && to.0 > max_x + margin)
+        || (from.1 > max_y + margin && to.1 > max_y + margin)
+    {
+        return;
+    }
+
+    let length = (to.0 - from.0).abs().max((to.1 - from.1).abs());
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let steps = length.ceil().clamp(1.0, 1e7) as u32;
+    for step in 0..=steps {
+        let t = f64::from(step) / f64::from(steps);
+        let cx = t.mul_add(to.0 - from.0, from.0).round() - offset;
+        let cy = t.mul_add(to.1 - from.1, from.1).round() - offset;
+        for dy in 0..=reach {
+            for dx in 0..=reach {
+                let (x, y) = (cx + f64::from(dx), cy + f64::from(dy));
+                if x >= 0.0 && y >= 0.0 && x < max_x && y < max_y {
+                    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                    img.put_pixel(x as u32, y as u32, color);
+                }
+            }
         }
+    }
+}
+
+/// Draws connected line segments through `points`.
+pub fn draw_polyline<P: Pixel>(
+    img: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    points: &[(f64, f64)],
+    width: u32,
+    color: P,
+) {
+    if let [point] = points {
+        draw_line(img, *point, *point, width, color);
+    }
+    for pair in points.windows(2) {
+        draw_line(img, pair[0], pair[1], width, color);
+    }
+}
+
+/// Picks the output color for pixel (x, y) from a palette entry's colors.
+type Pattern = fn(u32, u32, &[Rgb<u8>]) -> Rgb<u8>;
+
+fn full_color(_: u32, _: u32, c: &[Rgb<u8>]) -> Rgb<u8> {
+    c[0]
+}
+
+/// `c[0]` and `c[1]` in a checkerboard, `c[0]` where x and y are both even
+/// or both odd.
+fn fiddyfiddy(x: u32, y: u32, c: &[Rgb<u8>]) -> Rgb<u8> {
+    if x % 2 == y % 2 {
+        c[0]
     } else {
-        if (y % 2) == 1 {
-            return c[1];
-        }
+        c[1]
+    }
+}
+
+/// `c[1]` on one pixel of every 2x2 block, `c[0]` on the other three.
+fn quarter(x: u32, y: u32, c: &[Rgb<u8>]) -> Rgb<u8> {
+    if (x | y) & 1 == 0 {
+        return c[1];
     }
 
     c[0]
 }
-/*/
-fn threefourth(x: u32, y: u32, c: Vec<Rgb<u8>>) -> Rgb<u8> {
-    if (x % 4) == 0 {
-        if (y % 4) == 0 {
-            return c[1];
-        } else {
-            return c[0];
+
+/// Dithers a low-saturation pixel of brightness `level` between the two
+/// neighboring `grays`, black to white, in quarter steps: the darker gray,
+/// 25%, 50% and 75% of the lighter one, and the lighter one. With black and
+/// white only this gives five bands.
+fn gray_ramp(x: u32, y: u32, level: u8, grays: &[Rgb<u8>]) -> Rgb<u8> {
+    let Some(intervals) = grays.len().checked_sub(1).filter(|n| *n > 0) else {
+        return grays.first().copied().unwrap_or(BLACK);
+    };
+    // Rounded to the nearest quarter step, never halfway with 255 levels
+    let steps = 4 * intervals;
+    let step = (2 * usize::from(level) * steps + 255) / 510;
+    let Some(&[darker, lighter]) = grays.get(step / 4..=step / 4 + 1) else {
+        return grays[intervals];
+    };
+    match step % 4 {
+        0 => darker,
+        1 => quarter(x, y, &[darker, lighter]),
+        2 => fiddyfiddy(x, y, &[darker, lighter]),
+        _ => quarter(x, y, &[lighter, darker]),
+    }
+}
+
+/// A palette entry: the source color it stands for, and how to render it.
+type PaletteEntry = ([u8; 3], Pattern, Vec<Rgb<u8>>);
+
+fn generic_color_map() -> Vec<PaletteEntry> {
+    vec![
+        ([255, 255, 255], full_color, vec![WHITE]),
+        ([0, 0, 0], full_color, vec![BLACK]),
+        ([90, 90, 90], full_color, vec![BLACK]),
+        ([0, 0, 255], full_color, vec![BLUE]),
+        ([255, 0, 0], full_color, vec![RED]),
+        ([0, 255, 0], full_color, vec![GREEN]),
+        ([255, 127, 0], full_color, vec![ORANGE]),
+        ([255, 255, 0], full_color, vec![YELLOW]),
+        ([127, 127, 127], fiddyfiddy, vec![BLACK, WHITE]),
+        ([255, 255, 155], fiddyfiddy, vec![YELLOW, WHITE]),
+        ([127, 255, 127], fiddyfiddy, vec![GREEN, WHITE]),
+        ([212, 250, 212], fiddyfiddy, vec![GREEN, WHITE]),
+        ([251, 212, 157], fiddyfiddy, vec![RED, WHITE]),
+        ([127, 0, 255], fiddyfiddy, vec![RED, BLUE]),
+    ]
+}
+
+/// Color difference formula used to find the closest palette entry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorDistance {
+    /// Euclidean distance in Lab.
+    #[default]
+    Cie76,
+    /// CIE94 with graphic arts weights.
+    Cie94,
+    /// CIEDE2000, best for saturated greens and blues.
+    Ciede2000,
+}
+
+impl ColorDistance {
+    /// Color difference of `sample` from the palette color `reference`.
+    #[must_use]
+    pub fn delta_e(self, reference: &Lab, sample: &Lab) -> f32 {
+        match self {
+            Self::Cie76 => cie76(reference, sample),
+            Self::Cie94 => cie94(reference, sample),
+            Self::Ciede2000 => ciede2000(reference, sample),
         }
     }
+}
 
-    c[0]
+impl FromStr for ColorDistance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cie76" => Ok(Self::Cie76),
+            "cie94" => Ok(Self::Cie94),
+            "ciede2000" => Ok(Self::Ciede2000),
+            _ => Err(format!(
+                "unknown color distance '{s}', use cie76, cie94 or ciede2000"
+            )),
+        }
+    }
 }
-*/
 
-pub fn generic_map_color(x: u32, y: u32, pixel: Rgb<u8>) -> Rgb<u8> {
-    let color_map: [(Lab, fn(u32, u32, Vec<Rgb<u8>>) -> Rgb<u8>, Vec<Rgb<u8>>); 14] = [
-        (Lab::from_rgb(&[255, 255, 255]), full_color, vec![WHITE]),
-        (Lab::from_rgb(&[0, 0, 0]), full_color, vec![BLACK]),
-        (Lab::from_rgb(&[90, 90, 90]), full_color, vec![BLACK]),
-        (Lab::from_rgb(&[0, 0, 255]), full_color, vec![BLUE]),
-        (Lab::from_rgb(&[255, 0, 0]), full_color, vec![RED]),
-        (Lab::from_rgb(&[0, 255, 0]), full_color, vec![GREEN]),
-        (Lab::from_rgb(&[255, 127, 0]), full_color, vec![ORANGE]),
-        (Lab::from_rgb(&[255, 255, 0]), full_color, vec![YELLOW]),
-        (
-            Lab::from_rgb(&[127, 127, 127]),
-            fiddyfiddy,
-            vec![BLACK, WHITE],
-        ),
-        (
-            Lab::from_rgb(&[255, 255, 155]),
-            fiddyfiddy,
-            vec![YELLOW, WHITE],
-        ),
-        (
-            Lab::from_rgb(&[127, 255, 127]),
-            fiddyfiddy,
-            vec![GREEN, WHITE],
-        ),
-        (
-            Lab::from_rgb(&[212, 250, 212]),
-            fiddyfiddy,
-            vec![GREEN, WHITE],
-        ),
-        (
-            Lab::from_rgb(&[251, 212, 157]),
-            fiddyfiddy,
-            vec![RED, WHITE],
-        ),
-        (Lab::from_rgb(&[127, 0, 255]), fiddyfiddy, vec![RED, BLUE]),
-    ];
+impl fmt::Display for ColorDistance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cie76 => write!(f, "cie76"),
+            Self::Cie94 => write!(f, "cie94"),
+            Self::Ciede2000 => write!(f, "ciede2000"),
+        }
+    }
+}
+
+#[allow(clippy::suboptimal_flops)]
+fn cie76(reference: &Lab, sample: &Lab) -> f32 {
+    ((reference.l - sample.l).powi(2)
+        + (reference.a - sample.a).powi(2)
+        + (reference.b - sample.b).powi(2))
+    .sqrt()
+}
+
+#[allow(clippy::suboptimal_flops)]
+fn cie94(reference: &Lab, sample: &Lab) -> f32 {
+    let c1 = reference.a.hypot(reference.b);
+    let c2 = sample.a.hypot(sample.b);
+    let delta_l = reference.l - sample.l;
+    let delta_c = c1 - c2;
+    let delta_h_squared = ((reference.a - sample.a).powi(2) + (reference.b - sample.b).powi(2)
+        - delta_c.powi(2))
+    .max(0.0);
+
+    let s_c = 1.0 + 0.045 * c1;
+    let s_h = 1.0 + 0.015 * c1;
+    (delta_l.powi(2) + (delta_c / s_c).powi(2) + delta_h_squared / s_h.powi(2)).sqrt()
+}
 
-    #[allow(non_snake_case)]
-    let mut most_fitting_dE = f32::MAX;
-    let mut most_fitting_idx = 0;
-    for (idx, color) in color_map.iter().enumerate() {
-        let lab = Lab::from_rgb(&pixel.0);
-        #[allow(non_snake_case)]
-        let dE_squared = (lab.l - color.0.l).powf(2.0)
-            + (lab.a - color.0.a).powf(2.0)
-            + (lab.b - color.0.b).powf(2.0);
-        if dE_squared < most_fitting_dE {
-            most_fitting_idx = idx;
-            most_fitting_dE = dE_squared;
+/// CIEDE2000 as given by Sharma, Wu and Dalal (2005).
+#[allow(
+    clippy::suboptimal_flops,
+    clippy::similar_names,
+    clippy::manual_midpoint
+)]
+fn ciede2000(reference: &Lab, sample: &Lab) -> f32 {
+    let pow25_7 = 25_f32.powi(7);
+    let hue = |b: f32, a: f32| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
         }
+    };
+
+    let c_bar = (reference.a.hypot(reference.b) + sample.a.hypot(sample.b)) / 2.0;
+    let g = 0.5 * (1.0 - (c_bar.powi(7) / (c_bar.powi(7) + pow25_7)).sqrt());
+    let a1 = (1.0 + g) * reference.a;
+    let a2 = (1.0 + g) * sample.a;
+    let c1 = a1.hypot(reference.b);
+    let c2 = a2.hypot(sample.b);
+    let h1 = hue(reference.b, a1);
+    let h2 = hue(sample.b, a2);
+
+    let delta_l = sample.l - reference.l;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else if h2 - h1 < -180.0 {
+        h2 - h1 + 360.0
+    } else {
+        h2 - h1
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_bar = (reference.l + sample.l) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_bar).to_radians().cos()
+        + 0.32 * (3.0 * h_bar + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_bar - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_bar.powi(7) / (c_bar.powi(7) + pow25_7)).sqrt();
+    let s_l = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    ((delta_l / s_l).powi(2)
+        + (delta_c / s_c).powi(2)
+        + (delta_h / s_h).powi(2)
+        + r_t * (delta_c / s_c) * (delta_h / s_h))
+        .sqrt()
+}
+
+/// Bits kept per channel when indexing the lookup table.
+const LUT_BITS: u32 = 6;
+
+/// Finds the nearest palette entry of a source color.
+///
+/// CIE76 compares every pixel with every entry like it always did; the
+/// costly CIE94 and CIEDE2000 use a table of the nearest entry, computed once
+/// per palette and distance for colors quantized to `LUT_BITS`.
+pub struct PaletteMatcher {
+    entries: Vec<PaletteEntry>,
+    labs: Vec<Lab>,
+    distance: ColorDistance,
+    lut: Option<Vec<u8>>,
+}
+
+impl PaletteMatcher {
+    /// # Panics
+    ///
+    /// Panics if `entries` is empty or has more than 256 entries.
+    #[must_use]
+    pub(crate) fn new(entries: Vec<PaletteEntry>, distance: ColorDistance) -> Self {
+        assert!(!entries.is_empty() && entries.len() <= 256);
+        let labs: Vec<Lab> = entries.iter().map(|e| Lab::from_rgb(&e.0)).collect();
+        let mut matcher = Self {
+            entries,
+            labs,
+            distance,
+            lut: None,
+        };
+        if distance == ColorDistance::Cie76 {
+            return matcher;
+        }
+
+        let levels = 1_u8 << LUT_BITS;
+        let widen = |v: u8| (v << (8 - LUT_BITS)) | (v >> (2 * LUT_BITS - 8));
+        let mut lut = Vec::with_capacity(1 << (3 * LUT_BITS));
+        for r in 0..levels {
+            for g in 0..levels {
+                for b in 0..levels {
+                    let lab = Lab::from_rgb(&[widen(r), widen(g), widen(b)]);
+                    lut.push(matcher.nearest(&lab));
+                }
+            }
+        }
+        matcher.lut = Some(lut);
+        matcher
+    }
+
+    /// Index of the entry with the smallest color difference to `lab`.
+    fn nearest(&self, lab: &Lab) -> u8 {
+        let mut most_fitting_de = f32::MAX;
+        let mut most_fitting_idx = 0;
+        for (idx, color) in self.labs.iter().enumerate() {
+            let de = self.distance.delta_e(color, lab);
+            if de < most_fitting_de {
+                most_fitting_idx = idx;
+                most_fitting_de = de;
+            }
+        }
+        u8::try_from(most_fitting_idx).unwrap_or(u8::MAX)
     }
-    let rgb = color_map[most_fitting_idx].1(x, y, color_map[most_fitting_idx].2.clone());
-    /*println!(
-        "{} {}",
-        format!("{:02x}{:02x}{:02x}", pixel[0], pixel[1], pixel[2])
-            .on_truecolor(pixel[0], pixel[1], pixel[2]),
-        format!(
-            "{:02x}{:02x}{:02x} = {}",
-            rgb[0], rgb[1], rgb[2], most_fitting_idx
-        )
-        .on_truecolor(rgb[0], rgb[1], rgb[2])
-    );*/
 
-    return rgb;
+    /// Shared matcher for the generic palette, built on first use.
+    #[must_use]
+    pub fn generic(distance: ColorDistance) -> &'static Self {
+        static CIE76: OnceLock<PaletteMatcher> = OnceLock::new();
+        static CIE94: OnceLock<PaletteMatcher> = OnceLock::new();
+        static CIEDE2000: OnceLock<PaletteMatcher> = OnceLock::new();
+
+        let cell = match distance {
+            ColorDistance::Cie76 => &CIE76,
+            ColorDistance::Cie94 => &CIE94,
+            ColorDistance::Ciede2000 => &CIEDE2000,
+        };
+        cell.get_or_init(|| Self::new(generic_color_map(), distance))
+    }
+
+    #[must_use]
+    pub fn map_color(&self, x: u32, y: u32, pixel: Rgb<u8>) -> Rgb<u8> {
+        let exact = self.entries.iter().position(|e| e.0 == pixel.0);
+        let idx = match (exact, &self.lut) {
+            (Some(idx), _) => idx,
+            (None, Some(lut)) => {
+                let shift = 8 - LUT_BITS;
+                let idx = usize::from(pixel[0] >> shift) << (2 * LUT_BITS)
+                    | usize::from(pixel[1] >> shift) << LUT_BITS
+                    | usize::from(pixel[2] >> shift);
+                usize::from(lut[idx])
+            }
+            (None, None) => usize::from(self.nearest(&Lab::from_rgb(&pixel.0))),
+        };
+        let entry = &self.entries[idx];
+        entry.1(x, y, &entry.2)
+    }
 }
 
-pub fn outdoor_map_color(x: u32, y: u32, pixel: Rgb<u8>) -> Rgb<u8> {
-    let color_map: [(Rgb<u8>, fn(u32, u32, Vec<Rgb<u8>>) -> Rgb<u8>, Vec<Rgb<u8>>); 113] = [
+#[must_use]
+pub fn generic_map_color(x: u32, y: u32, pixel: Rgb<u8>) -> Rgb<u8> {
+    PaletteMatcher::generic(ColorDistance::Cie76).map_color(x, y, pixel)
+}
+
+/// `grays` are the neutral colors of the panel, black to white. Most of the
+/// function is the color table, one line per source color.
+#[allow(clippy::too_many_lines)]
+fn outdoor_table_color(x: u32, y: u32, pixel: Rgb<u8>, grays: &[Rgb<u8>]) -> Option<Rgb<u8>> {
+    let color_map: [(Rgb<u8>, Pattern, Vec<Rgb<u8>>); 113] = [
         (Rgb([255, 255, 255]), full_color, vec![WHITE]),
         (Rgb([0, 0, 0]), full_color, vec![BLACK]),
         (Rgb([0, 0, 255]), full_color, vec![BLUE]),
@@ -261,81 +603,632 @@ pub fn outdoor_map_color(x: u32, y: u32, pixel: Rgb<u8>) -> Rgb<u8> {
         .min()
         .unwrap();
     if max - min < 21 {
-        if max > 80 {
-            return WHITE;
-        } else if max > 200 {
-            return BLACK;
-        } else {
-            fiddyfiddy(x, y, vec![BLACK, WHITE]);
-        }
+        let sum: u16 = pixel.0.iter().copied().map(u16::from).sum();
+        let level = u8::try_from(sum / 3).unwrap_or(u8::MAX);
+        return Some(gray_ramp(x, y, level, grays));
     }
 
     // check if we have this color
-    for (_idx, color) in color_map.iter().enumerate() {
-        if pixel[0] == color.0[0] && pixel[1] == color.0[1] && pixel[2] == color.0[2] {
-            return color.1(x, y, color.2.clone());
+    color_map
+        .iter()
+        .find(|color| color.0 == pixel)
+        .map(|color| color.1(x, y, &color.2))
+}
+
+/// Maps a pixel with the hand-tuned outdoor table and falls back to the
+/// nearest Lab match of `generic_map_color` for colors the table lacks.
+#[must_use]
+pub fn outdoor_map_color(x: u32, y: u32, pixel: Rgb<u8>) -> Rgb<u8> {
+    outdoor_table_color(x, y, pixel, &[BLACK, WHITE])
+        .unwrap_or_else(|| generic_map_color(x, y, pixel))
+}
+
+/// Like `outdoor_map_color`, but records every color missing from the
+/// table in `unmatched`.
+pub fn outdoor_map_color_reporting(
+    x: u32,
+    y: u32,
+    pixel: Rgb<u8>,
+    unmatched: &mut UnmatchedColors,
+) -> Rgb<u8> {
+    outdoor_color_with(
+        x,
+        y,
+        pixel,
+        &[BLACK, WHITE],
+        PaletteMatcher::generic(ColorDistance::Cie76),
+        unmatched,
+    )
+}
+
+fn outdoor_color_with(
+    x: u32,
+    y: u32,
+    pixel: Rgb<u8>,
+    grays: &[Rgb<u8>],
+    fallback: &PaletteMatcher,
+    unmatched: &mut UnmatchedColors,
+) -> Rgb<u8> {
+    outdoor_table_color(x, y, pixel, grays).unwrap_or_else(|| {
+        unmatched.record(pixel);
+        fallback.map_color(x, y, pixel)
+    })
+}
+
+/// Pixel counts of source colors the outdoor table has no entry for.
+#[derive(Clone, Debug, Default)]
+pub struct UnmatchedColors {
+    counts: HashMap<Rgb<u8>, u64>,
+}
+
+impl UnmatchedColors {
+    pub fn record(&mut self, pixel: Rgb<u8>) {
+        *self.counts.entry(pixel).or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (pixel, count) in &other.counts {
+            *self.counts.entry(*pixel).or_insert(0) += count;
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    #[must_use]
+    pub fn count(&self, pixel: Rgb<u8>) -> u64 {
+        self.counts.get(&pixel).copied().unwrap_or(0)
+    }
+
+    /// One outdoor table entry per color, most frequent first, with the
+    /// color `generic_map_color` picked for it as a starting point.
+    #[must_use]
+    pub fn report(&self) -> String {
+        let mut colors: Vec<(&Rgb<u8>, &u64)> = self.counts.iter().collect();
+        colors.sort_by(|a, b| b.1.cmp(a.1).then(a.0 .0.cmp(&b.0 .0)));
+
+        let mut report = String::new();
+        for (pixel, count) in colors {
+            let fallback = generic_map_color(0, 0, *pixel);
+            let _ = writeln!(
+                report,
+                "(Rgb([{:#04x}, {:#04x}, {:#04x}]), full_color, vec![{}]), // {count} px",
+                pixel[0],
+                pixel[1],
+                pixel[2],
+                color_name(fallback).unwrap_or("RED"),
+            );
         }
+        report
     }
-    println!(
-        "{}",
-        format!(
-            "(Rgb([{:#04x},{:#04x},{:#04x}]), full_color, vec![BLACK]), ",
-            pixel[0], pixel[1], pixel[2],
-        )
-        .on_truecolor(pixel[0], pixel[1], pixel[2])
-    );
+}
 
-    return RED;
+/// Name of a palette color, as used in the color tables.
+#[must_use]
+pub const fn color_name(c: Rgb<u8>) -> Option<&'static str> {
+    match c {
+        BLACK => Some("BLACK"),
+        WHITE => Some("WHITE"),
+        RED => Some("RED"),
+        BLUE => Some("BLUE"),
+        GREEN => Some("GREEN"),
+        YELLOW => Some("YELLOW"),
+        ORANGE => Some("ORANGE"),
+        DARK_GRAY => Some("DARK_GRAY"),
+        LIGHT_GRAY => Some("LIGHT_GRAY"),
+        _ => None,
+    }
 }
 
-/*
-fn encode_png<P, Container>(img: &ImageBuffer<P, Container>) -> Result<Vec<u8>, ImageError>
-where
-    P: Pixel<Subpixel = u8> + 'static,
-    Container: Deref<Target = [P::Subpixel]>,
-{
+/// Palette color of a name as `color_name` gives it, ignoring case.
+#[must_use]
+pub fn color_by_name(name: &str) -> Option<Rgb<u8>> {
+    [
+        BLACK, WHITE, RED, BLUE, GREEN, YELLOW, ORANGE, DARK_GRAY, LIGHT_GRAY,
+    ]
+    .into_iter()
+    .find(|c| color_name(*c).is_some_and(|n| n.eq_ignore_ascii_case(name)))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMapping {
+    #[default]
+    Generic,
+    Outdoor,
+}
+
+impl FromStr for ColorMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "generic" => Ok(Self::Generic),
+            "outdoor" => Ok(Self::Outdoor),
+            _ => Err(format!(
+                "unknown color mapping '{s}', use generic or outdoor"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColorMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Generic => write!(f, "generic"),
+            Self::Outdoor => write!(f, "outdoor"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConvertOptions {
+    pub mapping: ColorMapping,
+    pub distance: ColorDistance,
+    pub panel: PanelProfile,
+    pub packing: PixelPacking,
+    /// Shows through transparent parts of the source tile.
+    pub background: Rgb<u8>,
+    /// Scales source tiles larger than `TILE_SIZE`.
+    pub filter: ResampleFilter,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            mapping: ColorMapping::default(),
+            distance: ColorDistance::default(),
+            panel: PanelProfile::default(),
+            packing: PixelPacking::default(),
+            background: WHITE,
+            filter: ResampleFilter::default(),
+        }
+    }
+}
+
+pub struct ConvertedTile {
+    pub raw: Vec<u8>,
+    /// The tile in palette colors, as the display will show it.
+    pub preview: RgbImage,
+    pub unmatched: UnmatchedColors,
+    /// Source colors mapped to a color the panel cannot show, written as raw
+    /// value 0.
+    pub unshown: UnmatchedColors,
+    /// The source tile was fully transparent, only background is left.
+    pub empty: bool,
+}
+
+/// Parses a color written as `RRGGBB` hex digits, with an optional `#`.
+///
+/// # Errors
+///
+/// Fails for anything but six hex digits.
+pub fn parse_color(s: &str) -> Result<Rgb<u8>, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+    };
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok(Rgb([r, g, b])),
+        _ => Err(format!("'{s}' is not a color, use RRGGBB")),
+    }
+}
+
+/// Blends `pixel` over `background` by its alpha.
+fn composite(pixel: Rgba<u8>, background: Rgb<u8>) -> Rgb<u8> {
+    let alpha = u16::from(pixel[3]);
+    Rgb([0, 1, 2].map(|c| {
+        let blended =
+            (u16::from(pixel[c]) * alpha + u16::from(background[c]) * (255 - alpha) + 127) / 255;
+        u8::try_from(blended).unwrap_or(u8::MAX)
+    }))
+}
+
+/// Encodes an image as PNG, e.g. the preview of a converted tile.
+///
+/// # Errors
+///
+/// Fails if the PNG encoder rejects the image.
+pub fn encode_png(img: &RgbImage) -> Result<Vec<u8>, ImageError> {
     let mut buf = Vec::new();
-    let encoder = image::codecs::png::PngEncoder::new(&mut buf);
-    encoder.write_image(img, img.width(), img.height(), image::ColorType::Rgb8)?;
+    img.write_to(&mut Cursor::new(&mut buf), ImageFormat::Png)?;
     Ok(buf)
 }
-*/
+
+/// Converts an encoded source tile into packed raw pixels with the default
+/// options.
+///
+/// # Errors
+///
+/// Fails if the image data cannot be decoded.
 pub fn convert_image(image_data: &[u8]) -> Result<Vec<u8>, ImageError> {
-    let in_img = ImageReader::new(Cursor::new(image_data))
+    convert_image_with(image_data, &ConvertOptions::default()).map(|tile| tile.raw)
+}
+
+/// Converts an encoded source tile into packed raw pixels.
+///
+/// # Errors
+///
+/// Fails if the image data cannot be decoded.
+pub fn convert_image_with(
+    image_data: &[u8],
+    options: &ConvertOptions,
+) -> Result<ConvertedTile, ImageError> {
+    Ok(convert_tile(decode_tile(image_data)?, options))
+}
+
+/// Decodes a source tile in any format the `image` crate knows.
+///
+/// # Errors
+///
+/// Fails if the image data cannot be decoded.
+pub fn decode_tile(image_data: &[u8]) -> Result<DynamicImage, ImageError> {
+    ImageReader::new(Cursor::new(image_data))
         .with_guessed_format()?
-        .decode()?;
+        .decode()
+}
+
+/// Converts a decoded source tile into packed raw pixels. Tiles larger than
+/// `TILE_SIZE` are scaled down first, transparent parts are composited
+/// against the background color.
+#[must_use]
+pub fn convert_tile(in_img: DynamicImage, options: &ConvertOptions) -> ConvertedTile {
+    convert_picture(&fit_tile(in_img, options.filter), options)
+}
 
+/// Converts an image of any size into packed raw pixels the way
+/// `convert_tile` does, for pictures that are not map tiles.
+#[must_use]
+pub fn convert_picture(in_img: &DynamicImage, options: &ConvertOptions) -> ConvertedTile {
     let (w, h) = in_img.dimensions();
     let mut output = RgbImage::new(w, h); // create a new buffer for our output
 
-    let mut pxl: u8 = 0;
-    let mut high = true;
-    let mut raw = Vec::new();
+    let panel = &options.panel;
+    let mut values = Vec::new();
+    let mut unmatched = UnmatchedColors::default();
+    let mut unshown = UnmatchedColors::default();
+    let matcher = panel.matcher(options.distance);
+    let grays = panel.grays();
+    let empty = in_img.color().has_alpha() && in_img.pixels().all(|(_, _, p)| p[3] == 0);
     for (x, y, pixel) in in_img.pixels() {
-        let rgb_pixel = image::Rgb([pixel[0], pixel[1], pixel[2]]);
-        let png_pixel = generic_map_color(x, y, rgb_pixel);
-        if high == true {
-            pxl = color_to_raw(png_pixel) << 4;
-        } else {
-            pxl += color_to_raw(png_pixel);
-            raw.push(pxl);
-        }
-
-        high = !high;
+        let rgb_pixel = composite(pixel, options.background);
+        let png_pixel = match options.mapping {
+            ColorMapping::Generic => matcher.map_color(x, y, rgb_pixel),
+            ColorMapping::Outdoor => {
+                let color = outdoor_color_with(x, y, rgb_pixel, &grays, matcher, &mut unmatched);
+                panel.nearest(color)
+            }
+        };
+        let value = panel.raw_value(png_pixel).unwrap_or_else(|| {
+            unshown.record(rgb_pixel);
+            0
+        });
+        values.push(value);
 
         output.put_pixel(x, y, png_pixel);
     }
+    let raw = options.packing.pack(&values, w);
 
-    Ok(raw)
+    ConvertedTile {
+        raw,
+        preview: output,
+        unmatched,
+        unshown,
+        empty,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn pictures_keep_their_size() {
+        let picture = DynamicImage::ImageRgb8(RgbImage::from_pixel(400, 10, BLACK));
+        let options = ConvertOptions::default();
+        let converted = convert_picture(&picture, &options);
+        assert_eq!(converted.preview.dimensions(), (400, 10));
+        assert_eq!(converted.raw.len(), options.packing.packed_len(400, 10));
+    }
+
     #[test]
     fn black_is_black() {
         let result = outdoor_map_color(0, 0, Rgb([0, 0, 0]));
         assert_eq!(result, Rgb([0, 0, 0]));
     }
+
+    fn lab(l: f32, a: f32, b: f32) -> Lab {
+        Lab { l, a, b }
+    }
+
+    #[test]
+    fn ciede2000_matches_reference_data() {
+        // pairs 1 and 2 of the Sharma, Wu and Dalal test data
+        let reference = lab(50.0, 0.0, -82.7485);
+        let de = ColorDistance::Ciede2000.delta_e(&reference, &lab(50.0, 2.6772, -79.7751));
+        assert!((de - 2.0425).abs() < 1e-3, "{de}");
+        let de = ColorDistance::Ciede2000.delta_e(&reference, &lab(50.0, 3.1571, -77.2803));
+        assert!((de - 2.8615).abs() < 1e-3, "{de}");
+    }
+
+    #[test]
+    fn cie94_weights_lightness_fully() {
+        let de = ColorDistance::Cie94.delta_e(&lab(50.0, 20.0, 20.0), &lab(60.0, 20.0, 20.0));
+        assert!((de - 10.0).abs() < 1e-4, "{de}");
+        let de = ColorDistance::Cie76.delta_e(&lab(50.0, 20.0, 20.0), &lab(50.0, 23.0, 24.0));
+        assert!((de - 5.0).abs() < 1e-4, "{de}");
+    }
+
+    #[test]
+    fn every_distance_keeps_primaries() {
+        for distance in [
+            ColorDistance::Cie76,
+            ColorDistance::Cie94,
+            ColorDistance::Ciede2000,
+        ] {
+            let matcher = PaletteMatcher::generic(distance);
+            for color in [BLACK, WHITE, RED, GREEN, BLUE, ORANGE] {
+                assert_eq!(matcher.map_color(0, 0, color), color, "{distance}");
+            }
+        }
+    }
+
+    #[test]
+    fn cie76_is_not_quantized() {
+        let entries = generic_color_map();
+        let matcher = PaletteMatcher::generic(ColorDistance::Cie76);
+        for r in (0..=255).step_by(5) {
+            for g in (0..=255).step_by(15) {
+                for b in (0..=255).step_by(15) {
+                    let pixel = Rgb([r, g, b]);
+                    let lab = Lab::from_rgb(&pixel.0);
+                    let nearest = entries
+                        .iter()
+                        .min_by(|a, b| {
+                            cie76(&Lab::from_rgb(&a.0), &lab)
+                                .total_cmp(&cie76(&Lab::from_rgb(&b.0), &lab))
+                        })
+                        .unwrap();
+                    assert_eq!(
+                        matcher.map_color(0, 0, pixel),
+                        nearest.1(0, 0, &nearest.2),
+                        "{pixel:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    fn white_pixels_in_block(level: u8) -> usize {
+        let pixel = Rgb([level, level, level]);
+        [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .filter(|(x, y)| outdoor_map_color(*x, *y, pixel) == WHITE)
+            .count()
+    }
+
+    #[test]
+    fn gray_ramp_black() {
+        assert_eq!(white_pixels_in_block(0), 0);
+        assert_eq!(white_pixels_in_block(31), 0);
+    }
+
+    #[test]
+    fn gray_ramp_quarter() {
+        assert_eq!(white_pixels_in_block(32), 1);
+        assert_eq!(white_pixels_in_block(64), 1);
+    }
+
+    #[test]
+    fn gray_ramp_half() {
+        assert_eq!(white_pixels_in_block(127), 2);
+    }
+
+    #[test]
+    fn gray_ramp_three_quarters() {
+        assert_eq!(white_pixels_in_block(192), 3);
+        assert_eq!(white_pixels_in_block(223), 3);
+    }
+
+    #[test]
+    fn gray_ramp_white() {
+        assert_eq!(white_pixels_in_block(224), 4);
+        assert_eq!(white_pixels_in_block(255), 4);
+    }
+
+    #[test]
+    fn gray_ramp_dithers_between_neighboring_panel_grays() {
+        let grays = PanelProfile::GRAY_4.grays();
+        assert_eq!(grays, [BLACK, DARK_GRAY, LIGHT_GRAY, WHITE]);
+        let block = |level: u8| {
+            [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(x, y)| gray_ramp(x, y, level, &grays))
+        };
+        assert_eq!(block(85), [DARK_GRAY; 4]);
+        assert_eq!(block(170), [LIGHT_GRAY; 4]);
+        let half = block(128);
+        assert_eq!(half.iter().filter(|c| **c == DARK_GRAY).count(), 2);
+        assert_eq!(half.iter().filter(|c| **c == LIGHT_GRAY).count(), 2);
+        let dark = block(30);
+        assert_eq!(dark.iter().filter(|c| **c == BLACK).count(), 3);
+        assert_eq!(dark.iter().filter(|c| **c == DARK_GRAY).count(), 1);
+        assert_eq!(block(250), [WHITE; 4]);
+
+        let options = ConvertOptions {
+            mapping: ColorMapping::Outdoor,
+            panel: PanelProfile::GRAY_4,
+            packing: PixelPacking::for_panel(&PanelProfile::GRAY_4),
+            ..ConvertOptions::default()
+        };
+        let picture = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([86, 86, 84])));
+        let tile = convert_picture(&picture, &options);
+        assert!(tile.preview.pixels().all(|p| *p == DARK_GRAY));
+    }
+
+    #[test]
+    fn gray_ramp_uses_brightness_of_slightly_tinted_pixels() {
+        let pixel = Rgb([0xd3, 0xd3, 0xce]);
+        let whites = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .filter(|(x, y)| outdoor_map_color(*x, *y, pixel) == WHITE)
+            .count();
+        assert_eq!(whites, 3);
+    }
+
+    #[test]
+    fn preview_shows_mapped_colors() {
+        let mut source = RgbImage::new(2, 1);
+        source.put_pixel(0, 0, Rgb([250, 5, 5]));
+        source.put_pixel(1, 0, Rgb([5, 5, 250]));
+        let tile =
+            convert_image_with(&encode_png(&source).unwrap(), &ConvertOptions::default()).unwrap();
+        assert_eq!(tile.preview.get_pixel(0, 0), &RED);
+        assert_eq!(tile.preview.get_pixel(1, 0), &BLUE);
+        assert_eq!(tile.raw, vec![0x43]);
+    }
+
+    #[test]
+    fn transparency_shows_the_background() {
+        let mut source = image::RgbaImage::new(3, 1);
+        source.put_pixel(0, 0, Rgba([5, 5, 250, 0]));
+        source.put_pixel(1, 0, Rgba([5, 5, 250, 255]));
+        source.put_pixel(2, 0, Rgba([0, 0, 0, 128]));
+        let mut png = Vec::new();
+        source
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let options = ConvertOptions {
+            background: GREEN,
+            ..ConvertOptions::default()
+        };
+        let tile = convert_image_with(&png, &options).unwrap();
+        assert!(!tile.empty);
+        assert_eq!(tile.preview.get_pixel(0, 0), &GREEN);
+        assert_eq!(tile.preview.get_pixel(1, 0), &BLUE);
+        assert_eq!(composite(Rgba([0, 0, 0, 128]), WHITE), Rgb([127, 127, 127]));
+    }
+
+    #[test]
+    fn fully_transparent_tile_is_empty() {
+        let source = image::RgbaImage::new(2, 2);
+        let mut png = Vec::new();
+        source
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let tile = convert_image_with(&png, &ConvertOptions::default()).unwrap();
+        assert!(tile.empty);
+        assert!(tile.preview.pixels().all(|p| *p == WHITE));
+    }
+
+    #[test]
+    fn colors_by_name() {
+        assert_eq!(color_by_name("red"), Some(RED));
+        assert_eq!(color_by_name("Dark_Gray"), Some(DARK_GRAY));
+        assert_eq!(color_by_name("purple"), None);
+    }
+
+    #[test]
+    fn colors_parse_as_hex() {
+        assert_eq!(parse_color("#ff8000"), Ok(Rgb([255, 128, 0])));
+        assert_eq!(parse_color("00FF00"), Ok(GREEN));
+        assert!(parse_color("fff").is_err());
+        assert!(parse_color("gg0000").is_err());
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let mut source = RgbImage::new(4, 2);
+        for (i, pixel) in source.pixels_mut().enumerate() {
+            *pixel = [BLACK, WHITE, RED, GREEN, BLUE, ORANGE, BLACK, WHITE][i];
+        }
+        let tile =
+            convert_image_with(&encode_png(&source).unwrap(), &ConvertOptions::default()).unwrap();
+        let (panel, packing) = (PanelProfile::ACEP_7, PixelPacking::default());
+        assert_eq!(
+            raw_to_image(&tile.raw, 4, 2, &panel, &packing).unwrap(),
+            tile.preview
+        );
+        assert!(raw_to_image(&tile.raw, 4, 4, &panel, &packing).is_err());
+        assert_eq!(
+            packing.unpack(&tile.raw, 4, 2).unwrap(),
+            vec![0, 1, 4, 2, 3, 6, 0, 1]
+        );
+    }
+
+    #[test]
+    fn odd_width_round_trips_through_decoder() {
+        let mut source = RgbImage::new(3, 3);
+        for (i, pixel) in source.pixels_mut().enumerate() {
+            *pixel = [BLACK, WHITE, RED, GREEN, BLUE, ORANGE][i % 6];
+        }
+        let panel = PanelProfile::ACEP_7;
+        for packing in [
+            PixelPacking::default(),
+            PixelPacking {
+                bits_per_pixel: 8,
+                order: BitOrder::LsbFirst,
+                row_padding: false,
+            },
+            PixelPacking {
+                bits_per_pixel: 4,
+                order: BitOrder::LsbFirst,
+                row_padding: true,
+            },
+        ] {
+            let options = ConvertOptions {
+                packing,
+                ..ConvertOptions::default()
+            };
+            let tile = convert_image_with(&encode_png(&source).unwrap(), &options).unwrap();
+            assert_eq!(
+                raw_to_image(&tile.raw, 3, 3, &panel, &packing).unwrap(),
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn lines_are_clipped_and_widened() {
+        let mut img = RgbImage::new(8, 8);
+        draw_polyline(&mut img, &[(-4.0, 2.0), (20.0, 2.0)], 3, RED);
+        assert_eq!(img.get_pixel(0, 1), &RED);
+        assert_eq!(img.get_pixel(7, 3), &RED);
+        assert_eq!(img.get_pixel(7, 4), &BLACK);
+        assert_eq!(img.get_pixel(3, 0), &BLACK);
+    }
+
+    #[test]
+    fn other_panels_pack_their_own_raw_values() {
+        let mut source = RgbImage::new(8, 1);
+        source.put_pixel(1, 0, WHITE);
+        source.put_pixel(7, 0, WHITE);
+        let options = ConvertOptions {
+            panel: PanelProfile::BW,
+            packing: PixelPacking::for_panel(&PanelProfile::BW),
+            ..ConvertOptions::default()
+        };
+        let tile = convert_image_with(&encode_png(&source).unwrap(), &options).unwrap();
+        assert_eq!(tile.raw, vec![0b0100_0001]);
+    }
+
+    #[test]
+    fn unknown_outdoor_color_falls_back_to_lab() {
+        let pixel = Rgb([0x10, 0x20, 0xf0]);
+        let mut unmatched = UnmatchedColors::default();
+        let result = outdoor_map_color_reporting(0, 0, pixel, &mut unmatched);
+        assert_eq!(result, BLUE);
+        outdoor_map_color_reporting(1, 0, pixel, &mut unmatched);
+        assert_eq!(unmatched.count(pixel), 2);
+        assert_eq!(
+            unmatched.report(),
+            "(Rgb([0x10, 0x20, 0xf0]), full_color, vec![BLUE]), // 2 px\n"
+        );
+    }
 }
@@ -0,0 +1,276 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::PanelProfile;
+
+/// Where the first pixel of a byte goes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BitOrder {
+    /// First pixel in the most significant bits, e.g. the high nibble.
+    #[default]
+    MsbFirst,
+    /// First pixel in the least significant bits.
+    LsbFirst,
+}
+
+impl FromStr for BitOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "msb" => Ok(Self::MsbFirst),
+            "lsb" => Ok(Self::LsbFirst),
+            _ => Err(format!("unknown bit order '{s}', use msb or lsb")),
+        }
+    }
+}
+
+impl fmt::Display for BitOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MsbFirst => write!(f, "msb"),
+            Self::LsbFirst => write!(f, "lsb"),
+        }
+    }
+}
+
+/// How raw pixel values are laid out in the bytes of a tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelPacking {
+    /// 1, 2, 4 or 8.
+    pub bits_per_pixel: u8,
+    pub order: BitOrder,
+    /// Start every row on a new byte.
+    pub row_padding: bool,
+}
+
+impl PixelPacking {
+    /// The native layout of a panel: its bit depth, most significant bits
+    /// first and rows packed back to back.
+    #[must_use]
+    pub const fn for_panel(panel: &PanelProfile) -> Self {
+        Self {
+            bits_per_pixel: panel.bits_per_pixel,
+            order: BitOrder::MsbFirst,
+            row_padding: false,
+        }
+    }
+
+    /// Checks that the bit depth is supported and holds every raw value of
+    /// the panel.
+    ///
+    /// # Errors
+    ///
+    /// Describes the mismatch.
+    pub fn validate(&self, panel: &PanelProfile) -> Result<(), String> {
+        if ![1, 2, 4, 8].contains(&self.bits_per_pixel) {
+            return Err(format!(
+                "{} bits per pixel are not supported, use 1, 2, 4 or 8",
+                self.bits_per_pixel
+            ));
+        }
+        let max_raw = panel.palette.iter().map(|(_, raw)| *raw).max().unwrap_or(0);
+        if u32::from(max_raw) >= 1 << self.bits_per_pixel {
+            return Err(format!(
+                "{panel} needs more than {} bits per pixel",
+                self.bits_per_pixel
+            ));
+        }
+        Ok(())
+    }
+
+    /// Bytes taken by a `width` x `height` tile.
+    #[must_use]
+    pub fn packed_len(&self, width: u32, height: u32) -> usize {
+        let bits = usize::from(self.bits_per_pixel);
+        let (width, height) = (width as usize, height as usize);
+        if self.row_padding {
+            (width * bits).div_ceil(8) * height
+        } else {
+            (width * height * bits).div_ceil(8)
+        }
+    }
+
+    /// Packs one raw value per pixel, row by row, for rows of `width`
+    /// pixels. Unused bits of the last byte are zero.
+    #[must_use]
+    pub fn pack(&self, values: &[u8], width: u32) -> Vec<u8> {
+        let bits = u32::from(self.bits_per_pixel);
+        let mask = self.mask();
+        let width = width.max(1) as usize;
+
+        let mut raw = Vec::with_capacity((values.len() * bits as usize).div_ceil(8));
+        let mut byte = 0_u8;
+        let mut used = 0_u32;
+        for (i, value) in values.iter().enumerate() {
+            if self.row_padding && used > 0 && i % width == 0 {
+                raw.push(byte);
+                (byte, used) = (0, 0);
+            }
+            byte |= (value & mask) << self.shift(used);
+            used += bits;
+            if used == 8 {
+                raw.push(byte);
+                (byte, used) = (0, 0);
+            }
+        }
+        if used > 0 {
+            raw.push(byte);
+        }
+        raw
+    }
+
+    /// Inverse of `pack`, one raw value per pixel.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `raw` is not exactly the size of a `width` x `height` tile.
+    pub fn unpack(&self, raw: &[u8], width: u32, height: u32) -> Result<Vec<u8>, String> {
+        if raw.len() != self.packed_len(width, height) {
+            return Err(format!(
+                "{} bytes do not hold a {width}x{height} tile at {} bits per pixel",
+                raw.len(),
+                self.bits_per_pixel
+            ));
+        }
+
+        let bits = usize::from(self.bits_per_pixel);
+        let row_bits = if self.row_padding {
+            (width as usize * bits).div_ceil(8) * 8
+        } else {
+            width as usize * bits
+        };
+        let mut values = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let bit = y * row_bits + x * bits;
+                let used = u32::try_from(bit % 8).unwrap_or(0);
+                values.push((raw[bit / 8] >> self.shift(used)) & self.mask());
+            }
+        }
+        Ok(values)
+    }
+
+    /// Width and height of a square tile stored in `len` bytes.
+    #[must_use]
+    pub fn dimensions(&self, len: usize) -> Option<(u32, u32)> {
+        let side =
+            (1..=u32::try_from(len * 8).ok()?).find(|side| self.packed_len(*side, *side) >= len)?;
+        (self.packed_len(side, side) == len).then_some((side, side))
+    }
+
+    const fn mask(self) -> u8 {
+        u8::MAX >> (8 - self.bits_per_pixel)
+    }
+
+    /// Shift of a value that starts `used` bits into its byte.
+    const fn shift(self, used: u32) -> u32 {
+        match self.order {
+            BitOrder::MsbFirst => 8 - self.bits_per_pixel as u32 - used,
+            BitOrder::LsbFirst => used,
+        }
+    }
+}
+
+impl Default for PixelPacking {
+    fn default() -> Self {
+        Self::for_panel(&PanelProfile::ACEP_7)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn packing(bits_per_pixel: u8, order: BitOrder, row_padding: bool) -> PixelPacking {
+        PixelPacking {
+            bits_per_pixel,
+            order,
+            row_padding,
+        }
+    }
+
+    #[test]
+    fn default_keeps_high_nibble_first() {
+        let packing = PixelPacking::default();
+        assert_eq!(packing.pack(&[4, 3, 0, 1], 4), vec![0x43, 0x01]);
+        assert_eq!(packing.unpack(&[0x43], 2, 1).unwrap(), vec![4, 3]);
+    }
+
+    #[test]
+    fn odd_pixel_count_keeps_last_pixel() {
+        let packing = PixelPacking::default();
+        assert_eq!(packing.pack(&[4, 3, 5], 3), vec![0x43, 0x50]);
+        assert_eq!(packing.unpack(&[0x43, 0x50], 3, 1).unwrap(), vec![4, 3, 5]);
+    }
+
+    #[test]
+    fn bit_order_and_depth() {
+        let values = [1, 0, 1, 1, 0, 0, 0, 1, 1];
+        assert_eq!(
+            packing(1, BitOrder::MsbFirst, false).pack(&values, 9),
+            vec![0b1011_0001, 0b1000_0000]
+        );
+        assert_eq!(
+            packing(1, BitOrder::LsbFirst, false).pack(&values, 9),
+            vec![0b1000_1101, 0b0000_0001]
+        );
+        assert_eq!(
+            packing(2, BitOrder::LsbFirst, false).pack(&[3, 2, 1, 0], 4),
+            vec![0b0001_1011]
+        );
+        assert_eq!(
+            packing(8, BitOrder::MsbFirst, false).pack(&[6, 1], 2),
+            vec![6, 1]
+        );
+    }
+
+    #[test]
+    fn row_padding_starts_rows_on_a_byte() {
+        let packing = packing(4, BitOrder::MsbFirst, true);
+        assert_eq!(
+            packing.pack(&[1, 2, 3, 4, 5, 6], 3),
+            vec![0x12, 0x30, 0x45, 0x60]
+        );
+        assert_eq!(packing.packed_len(3, 2), 4);
+    }
+
+    #[test]
+    fn round_trips_every_layout() {
+        let (width, height) = (5, 3);
+        for bits in [1, 2, 4, 8] {
+            let values: Vec<u8> = (0..width * height)
+                .map(|i| u8::try_from(i * 7 % 251).unwrap() & (u8::MAX >> (8 - bits)))
+                .collect();
+            for order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+                for row_padding in [false, true] {
+                    let packing = packing(bits, order, row_padding);
+                    let raw = packing.pack(&values, width);
+                    assert_eq!(raw.len(), packing.packed_len(width, height));
+                    assert_eq!(packing.unpack(&raw, width, height).unwrap(), values);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn validates_against_panel() {
+        assert!(packing(2, BitOrder::MsbFirst, false)
+            .validate(&PanelProfile::ACEP_7)
+            .is_err());
+        assert!(packing(3, BitOrder::MsbFirst, false)
+            .validate(&PanelProfile::BW)
+            .is_err());
+        assert!(packing(8, BitOrder::MsbFirst, false)
+            .validate(&PanelProfile::ACEP_7)
+            .is_ok());
+    }
+
+    #[test]
+    fn square_dimensions() {
+        assert_eq!(PixelPacking::default().dimensions(32768), Some((256, 256)));
+        let padded = packing(4, BitOrder::MsbFirst, true);
+        assert_eq!(padded.dimensions(15), Some((5, 5)));
+        assert_eq!(padded.dimensions(12), None);
+    }
+}
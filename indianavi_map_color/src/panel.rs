@@ -0,0 +1,227 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use image::Rgb;
+use lab::Lab;
+
+use crate::{
+    fiddyfiddy, full_color, generic_color_map, ColorDistance, PaletteEntry, PaletteMatcher, BLACK,
+    BLUE, DARK_GRAY, GREEN, LIGHT_GRAY, ORANGE, RED, WHITE, YELLOW,
+};
+
+/// An e-paper panel: the colors it shows, the raw value of each color and
+/// how many bits a pixel takes in a tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PanelProfile {
+    pub name: &'static str,
+    /// Identifies the palette in tile headers.
+    pub id: u8,
+    pub bits_per_pixel: u8,
+    /// Display colors with their raw values.
+    pub palette: &'static [(Rgb<u8>, u8)],
+}
+
+impl PanelProfile {
+    /// The 7-color panel of the device.
+    pub const ACEP_7: Self = Self {
+        name: "acep7",
+        id: 0,
+        bits_per_pixel: 4,
+        palette: &[
+            (BLACK, 0),
+            (WHITE, 1),
+            (GREEN, 2),
+            (BLUE, 3),
+            (RED, 4),
+            (YELLOW, 5),
+            (ORANGE, 6),
+        ],
+    };
+    pub const BW: Self = Self {
+        name: "bw",
+        id: 1,
+        bits_per_pixel: 1,
+        palette: &[(BLACK, 0), (WHITE, 1)],
+    };
+    pub const BWR: Self = Self {
+        name: "bwr",
+        id: 2,
+        bits_per_pixel: 2,
+        palette: &[(BLACK, 0), (WHITE, 1), (RED, 2)],
+    };
+    pub const GRAY_4: Self = Self {
+        name: "gray4",
+        id: 3,
+        bits_per_pixel: 2,
+        palette: &[(BLACK, 0), (DARK_GRAY, 1), (LIGHT_GRAY, 2), (WHITE, 3)],
+    };
+    pub const SPECTRA_6: Self = Self {
+        name: "spectra6",
+        id: 4,
+        bits_per_pixel: 4,
+        palette: &[
+            (BLACK, 0),
+            (WHITE, 1),
+            (YELLOW, 2),
+            (RED, 3),
+            (BLUE, 5),
+            (GREEN, 6),
+        ],
+    };
+    pub const ALL: [Self; 5] = [
+        Self::ACEP_7,
+        Self::BW,
+        Self::BWR,
+        Self::GRAY_4,
+        Self::SPECTRA_6,
+    ];
+
+    #[must_use]
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.id == id)
+    }
+
+    /// Raw value of a palette color.
+    #[must_use]
+    pub fn raw_value(&self, color: Rgb<u8>) -> Option<u8> {
+        self.palette
+            .iter()
+            .find(|(c, _)| *c == color)
+            .map(|(_, raw)| *raw)
+    }
+
+    /// Palette color of a raw value.
+    #[must_use]
+    pub fn color(&self, raw: u8) -> Option<Rgb<u8>> {
+        self.palette
+            .iter()
+            .find(|(_, r)| *r == raw)
+            .map(|(c, _)| *c)
+    }
+
+    /// The neutral colors of the palette, black to white.
+    #[must_use]
+    pub fn grays(&self) -> Vec<Rgb<u8>> {
+        let mut grays: Vec<Rgb<u8>> = self
+            .palette
+            .iter()
+            .map(|(c, _)| *c)
+            .filter(|c| c[0] == c[1] && c[1] == c[2])
+            .collect();
+        grays.sort_by_key(|c| c[0]);
+        grays
+    }
+
+    /// The palette color closest to `color`, for colors chosen with another
+    /// panel in mind.
+    #[must_use]
+    pub fn nearest(&self, color: Rgb<u8>) -> Rgb<u8> {
+        if self.raw_value(color).is_some() {
+            return color;
+        }
+        let lab = Lab::from_rgb(&color.0);
+        self.palette
+            .iter()
+            .map(|(c, _)| (*c, ColorDistance::Cie76.delta_e(&Lab::from_rgb(&c.0), &lab)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(BLACK, |(c, _)| c)
+    }
+
+    /// Entries to match source colors against. The 7-color panel uses the
+    /// hand-tuned generic table, other panels their solid colors plus a
+    /// 50% dither of every pair.
+    #[must_use]
+    pub fn color_map(&self) -> Vec<PaletteEntry> {
+        if *self == Self::ACEP_7 {
+            return generic_color_map();
+        }
+
+        let mut entries: Vec<PaletteEntry> = self
+            .palette
+            .iter()
+            .map(|(c, _)| (c.0, full_color as _, vec![*c]))
+            .collect();
+        for (i, (a, _)) in self.palette.iter().enumerate() {
+            for (b, _) in &self.palette[i + 1..] {
+                let mix = [0, 1, 2].map(|ch| (a[ch] & b[ch]) + ((a[ch] ^ b[ch]) >> 1));
+                entries.push((mix, fiddyfiddy as _, vec![*a, *b]));
+            }
+        }
+        entries
+    }
+
+    /// Shared matcher for this panel, built on first use.
+    ///
+    /// # Panics
+    ///
+    /// Panics for profiles that are not in `PanelProfile::ALL`.
+    #[must_use]
+    pub fn matcher(&self, distance: ColorDistance) -> &'static PaletteMatcher {
+        const DISTANCES: usize = 3;
+        static MATCHERS: [OnceLock<PaletteMatcher>; PanelProfile::ALL.len() * DISTANCES] =
+            [const { OnceLock::new() }; PanelProfile::ALL.len() * DISTANCES];
+
+        if *self == Self::ACEP_7 {
+            return PaletteMatcher::generic(distance);
+        }
+        let panel = Self::ALL
+            .iter()
+            .position(|p| p == self)
+            .expect("a known panel profile");
+        let slot = panel * DISTANCES
+            + match distance {
+                ColorDistance::Cie76 => 0,
+                ColorDistance::Cie94 => 1,
+                ColorDistance::Ciede2000 => 2,
+            };
+        MATCHERS[slot].get_or_init(|| PaletteMatcher::new(self.color_map(), distance))
+    }
+}
+
+impl Default for PanelProfile {
+    fn default() -> Self {
+        Self::ACEP_7
+    }
+}
+
+impl FromStr for PanelProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter().find(|p| p.name == s).ok_or_else(|| {
+            let names: Vec<&str> = Self::ALL.iter().map(|p| p.name).collect();
+            format!("unknown panel '{s}', use {}", names.join(", "))
+        })
+    }
+}
+
+impl fmt::Display for PanelProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panels_map_to_their_own_palette() {
+        for panel in PanelProfile::ALL {
+            let matcher = panel.matcher(ColorDistance::Cie76);
+            for (color, raw) in panel.palette {
+                let mapped = matcher.map_color(0, 0, *color);
+                assert_eq!(panel.raw_value(mapped), Some(*raw), "{panel}");
+            }
+            assert!(panel.raw_value(panel.nearest(ORANGE)).is_some(), "{panel}");
+        }
+    }
+
+    #[test]
+    fn profiles_parse_by_name() {
+        assert_eq!("spectra6".parse(), Ok(PanelProfile::SPECTRA_6));
+        assert_eq!(PanelProfile::from_id(3), Some(PanelProfile::GRAY_4));
+        assert!("epd".parse::<PanelProfile>().is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use std::fmt;
+use std::str::FromStr;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, RgbaImage};
+
+use crate::TILE_SIZE;
+
+/// Filter used to scale source tiles that do not match the device tile size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleFilter {
+    Nearest,
+    #[default]
+    Triangle,
+    CatmullRom,
+    Lanczos3,
+}
+
+impl ResampleFilter {
+    const fn filter_type(self) -> FilterType {
+        match self {
+            Self::Nearest => FilterType::Nearest,
+            Self::Triangle => FilterType::Triangle,
+            Self::CatmullRom => FilterType::CatmullRom,
+            Self::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl FromStr for ResampleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Self::Nearest),
+            "triangle" => Ok(Self::Triangle),
+            "catmullrom" => Ok(Self::CatmullRom),
+            "lanczos3" => Ok(Self::Lanczos3),
+            _ => Err(format!(
+                "unknown filter '{s}', use nearest, triangle, catmullrom or lanczos3"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ResampleFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nearest => write!(f, "nearest"),
+            Self::Triangle => write!(f, "triangle"),
+            Self::CatmullRom => write!(f, "catmullrom"),
+            Self::Lanczos3 => write!(f, "lanczos3"),
+        }
+    }
+}
+
+/// How tiles are enlarged for zoom levels the provider does not serve.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UpscaleFilter {
+    /// Every pixel becomes a square block.
+    Nearest,
+    /// Scale2x, keeps diagonal lines and outlines from turning into steps.
+    #[default]
+    Edge,
+}
+
+impl FromStr for UpscaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Self::Nearest),
+            "edge" => Ok(Self::Edge),
+            _ => Err(format!("unknown upscale filter '{s}', use nearest or edge")),
+        }
+    }
+}
+
+impl fmt::Display for UpscaleFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nearest => write!(f, "nearest"),
+            Self::Edge => write!(f, "edge"),
+        }
+    }
+}
+
+/// Scales a tile larger than `TILE_SIZE`, e.g. a 512px or `@2x` tile, down
+/// to `TILE_SIZE`. Smaller tiles are returned as they are.
+#[must_use]
+pub fn fit_tile(img: DynamicImage, filter: ResampleFilter) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    if width <= TILE_SIZE && height <= TILE_SIZE {
+        return img;
+    }
+    img.resize_exact(TILE_SIZE, TILE_SIZE, filter.filter_type())
+}
+
+/// Splits a tile of zoom z into the four tiles of zoom z + 1 it covers.
+///
+/// The order is top left, top right, bottom left, bottom right. Tiles that
+/// are not twice `TILE_SIZE` are scaled to it first.
+#[must_use]
+pub fn split_tile(img: &DynamicImage, filter: ResampleFilter) -> [DynamicImage; 4] {
+    let size = 2 * TILE_SIZE;
+    let scaled;
+    let img = if img.dimensions() == (size, size) {
+        img
+    } else {
+        scaled = img.resize_exact(size, size, filter.filter_type());
+        &scaled
+    };
+    [(0, 0), (1, 0), (0, 1), (1, 1)]
+        .map(|(dx, dy)| img.crop_imm(dx * TILE_SIZE, dy * TILE_SIZE, TILE_SIZE, TILE_SIZE))
+}
+
+/// Cuts the tile `levels` zoom levels below `ancestor` at `offset` (in tiles
+/// of that zoom, counted from the top left of the ancestor) out of it and
+/// enlarges it to `TILE_SIZE`.
+#[must_use]
+pub fn overzoom_tile(
+    ancestor: &DynamicImage,
+    levels: u32,
+    (dx, dy): (u32, u32),
+    filter: UpscaleFilter,
+) -> DynamicImage {
+    let size = (ancestor.width() >> levels).max(1);
+    let mut tile = ancestor
+        .crop_imm(dx * size, dy * size, size, size)
+        .to_rgba8();
+    if filter == UpscaleFilter::Edge {
+        while tile.width() * 2 <= TILE_SIZE {
+            tile = scale2x(&tile);
+        }
+    }
+    if tile.width() != TILE_SIZE {
+        tile = imageops::resize(&tile, TILE_SIZE, TILE_SIZE, FilterType::Nearest);
+    }
+    DynamicImage::ImageRgba8(tile)
+}
+
+/// Doubles the image with the Scale2x (EPX) rule: a corner takes the color of
+/// its two neighbours where they agree and form an edge.
+fn scale2x(img: &RgbaImage) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    let mut out = RgbaImage::new(width * 2, height * 2);
+    for y in 0..height {
+        for x in 0..width {
+            let p = *img.get_pixel(x, y);
+            let above = *img.get_pixel(x, y.saturating_sub(1));
+            let right = *img.get_pixel((x + 1).min(width - 1), y);
+            let left = *img.get_pixel(x.saturating_sub(1), y);
+            let below = *img.get_pixel(x, (y + 1).min(height - 1));
+
+            let corner = |a, b, c, d| if a == b && a != c && b != d { a } else { p };
+            out.put_pixel(2 * x, 2 * y, corner(left, above, below, right));
+            out.put_pixel(2 * x + 1, 2 * y, corner(above, right, left, below));
+            out.put_pixel(2 * x, 2 * y + 1, corner(below, left, right, above));
+            out.put_pixel(2 * x + 1, 2 * y + 1, corner(right, below, above, left));
+        }
+    }
+    out
+}
+
+/// Joins the four tiles of zoom z + 1 covering a tile of zoom z and scales
+/// them down to one `TILE_SIZE` tile.
+///
+/// Takes the tiles in the order `split_tile` returns them.
+#[must_use]
+pub fn merge_tiles(tiles: [DynamicImage; 4], filter: ResampleFilter) -> DynamicImage {
+    let mut canvas = RgbaImage::new(2 * TILE_SIZE, 2 * TILE_SIZE);
+    for (tile, (dx, dy)) in tiles.into_iter().zip([(0, 0), (1, 0), (0, 1), (1, 1)]) {
+        let tile = if tile.dimensions() == (TILE_SIZE, TILE_SIZE) {
+            tile
+        } else {
+            tile.resize_exact(TILE_SIZE, TILE_SIZE, filter.filter_type())
+        };
+        imageops::replace(
+            &mut canvas,
+            &tile.to_rgba8(),
+            i64::from(dx * TILE_SIZE),
+            i64::from(dy * TILE_SIZE),
+        );
+    }
+    DynamicImage::ImageRgba8(imageops::resize(
+        &canvas,
+        TILE_SIZE,
+        TILE_SIZE,
+        filter.filter_type(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn quadrants() -> DynamicImage {
+        let colors = [[10, 0, 0], [20, 0, 0], [30, 0, 0], [40, 0, 0]];
+        DynamicImage::ImageRgb8(RgbImage::from_fn(2 * TILE_SIZE, 2 * TILE_SIZE, |x, y| {
+            let quadrant = usize::from(x >= TILE_SIZE) + 2 * usize::from(y >= TILE_SIZE);
+            Rgb(colors[quadrant])
+        }))
+    }
+
+    #[test]
+    fn large_tiles_are_scaled_to_tile_size() {
+        let tile = fit_tile(quadrants(), ResampleFilter::Triangle);
+        assert_eq!(tile.dimensions(), (TILE_SIZE, TILE_SIZE));
+        let small = DynamicImage::ImageRgb8(RgbImage::new(3, 2));
+        assert_eq!(
+            fit_tile(small, ResampleFilter::Nearest).dimensions(),
+            (3, 2)
+        );
+    }
+
+    #[test]
+    fn merge_undoes_split() {
+        let merged = merge_tiles(
+            split_tile(&quadrants(), ResampleFilter::Nearest),
+            ResampleFilter::Nearest,
+        );
+        assert_eq!(merged.dimensions(), (TILE_SIZE, TILE_SIZE));
+        let quarter = TILE_SIZE / 4;
+        assert_eq!(merged.get_pixel(quarter, quarter)[0], 10);
+        assert_eq!(merged.get_pixel(3 * quarter, quarter)[0], 20);
+        assert_eq!(merged.get_pixel(quarter, 3 * quarter)[0], 30);
+        assert_eq!(merged.get_pixel(3 * quarter, 3 * quarter)[0], 40);
+    }
+
+    #[test]
+    fn overzoom_enlarges_one_part() {
+        let tile = overzoom_tile(&quadrants(), 2, (2, 1), UpscaleFilter::Nearest);
+        assert_eq!(tile.dimensions(), (TILE_SIZE, TILE_SIZE));
+        assert!(tile.pixels().all(|(_, _, p)| p[0] == 20));
+    }
+
+    #[test]
+    fn scale2x_keeps_diagonals() {
+        let (black, white) = (image::Rgba([0, 0, 0, 255]), image::Rgba([255; 4]));
+        let img = RgbaImage::from_fn(2, 2, |x, y| if x == y { black } else { white });
+        let scaled = scale2x(&img);
+        assert_eq!(scaled.get_pixel(2, 1), &black);
+        assert_eq!(scaled.get_pixel(3, 0), &white);
+        assert_eq!(scaled.get_pixel(0, 0), &black);
+    }
+
+    #[test]
+    fn split_keeps_quadrant_order() {
+        let tiles = split_tile(&quadrants(), ResampleFilter::Nearest);
+        for (i, tile) in tiles.iter().enumerate() {
+            assert_eq!(tile.dimensions(), (TILE_SIZE, TILE_SIZE));
+            let expected = u8::try_from(10 * (i + 1)).unwrap();
+            assert_eq!(tile.get_pixel(TILE_SIZE / 2, TILE_SIZE / 2)[0], expected);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Byte run-length encoding of packed tiles, cheap enough to decode on the
+//! device.
+//!
+//! The stream is a sequence of packets, each starting with a control byte `c`:
+//!
+//! - `c < 0x80`: the next `c + 1` bytes are copied as they are.
+//! - `c >= 0x80`: the next byte is repeated `(c & 0x7f) + 1` times.
+
+/// Longest literal or run one packet holds.
+const MAX_PACKET: usize = 128;
+/// Shorter runs are cheaper as part of a literal.
+const MIN_RUN: usize = 3;
+
+/// Encodes packed tile data.
+#[must_use]
+pub fn rle_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 4);
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(MAX_PACKET)
+            .take_while(|b| **b == data[i])
+            .count();
+        if run >= MIN_RUN {
+            push_literals(&mut out, &data[literal_start..i]);
+            out.push(0x80 | packet_count(run));
+            out.push(data[i]);
+            literal_start = i + run;
+        }
+        i += run;
+    }
+    push_literals(&mut out, &data[literal_start..]);
+    out
+}
+
+/// Decodes `data` back into `len` bytes of packed tile data.
+///
+/// # Errors
+///
+/// Fails if the stream ends inside a packet or does not decode to exactly
+/// `len` bytes.
+pub fn rle_decode(data: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(len);
+    let mut bytes = data.iter();
+    while let Some(control) = bytes.next() {
+        let count = usize::from(control & 0x7f) + 1;
+        if control & 0x80 == 0 {
+            let literal = bytes
+                .as_slice()
+                .get(..count)
+                .ok_or("literal is truncated")?;
+            out.extend_from_slice(literal);
+            bytes.nth(count - 1);
+        } else {
+            let value = bytes.next().ok_or("run is truncated")?;
+            out.resize(out.len() + count, *value);
+        }
+        if out.len() > len {
+            return Err(format!("decodes to more than {len} bytes"));
+        }
+    }
+    if out.len() != len {
+        return Err(format!("decodes to {} bytes instead of {len}", out.len()));
+    }
+    Ok(out)
+}
+
+fn push_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_PACKET) {
+        out.push(packet_count(chunk.len()));
+        out.extend_from_slice(chunk);
+    }
+}
+
+/// Count field of a packet of `len` bytes, 1 to 128.
+#[allow(clippy::cast_possible_truncation)]
+const fn packet_count(len: usize) -> u8 {
+    (len - 1) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_and_literals() {
+        let data = [1, 2, 2, 3, 3, 3, 3, 4];
+        let encoded = rle_encode(&data);
+        assert_eq!(encoded, vec![2, 1, 2, 2, 0x83, 3, 0, 4]);
+        assert_eq!(rle_decode(&encoded, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn uniform_tile_shrinks() {
+        let data = vec![0x11; 32 * 1024];
+        let encoded = rle_encode(&data);
+        assert_eq!(encoded.len(), 2 * 256);
+        assert_eq!(rle_decode(&encoded, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn long_literals_round_trip() {
+        let data: Vec<u8> = (0..1000_u32)
+            .map(|i| u8::try_from(i * 7 % 251).unwrap())
+            .chain([9; 300])
+            .collect();
+        assert_eq!(rle_decode(&rle_encode(&data), data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_broken_streams() {
+        assert!(rle_decode(&[3, 1, 2], 4).is_err());
+        assert!(rle_decode(&[0x85], 6).is_err());
+        assert!(rle_decode(&[0x85, 1], 4).is_err());
+        assert!(rle_decode(&[0x85, 1], 8).is_err());
+    }
+}
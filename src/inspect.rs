@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+
+use colored::Colorize;
+use image::RgbImage;
+use indianavi_map_color::{PanelProfile, PixelPacking, TileHeader};
+
+/// Prints header, size and palette histogram of a raw tile, optionally
+/// rendering it. A header overrides the panel, packing and width given.
+pub fn run(
+    file: &Path,
+    width: Option<u32>,
+    png: Option<&Path>,
+    terminal: Option<u32>,
+    panel: &PanelProfile,
+    packing: &PixelPacking,
+) -> Result<(), String> {
+    let file_data = fs::read(file).map_err(|e| format!("{}: {e}", file.display()))?;
+    let (header, stored) = TileHeader::split(&file_data)?;
+    let mut raw = Cow::Borrowed(stored);
+    let (panel, packing, width) = match header {
+        Some(header) => {
+            println!(
+                "Header v{}: tile {}/{}/{}, {}x{}, {} at {} bits per pixel, {} first{}{}, CRC32 {:08x}",
+                header.version,
+                header.zoom,
+                header.x,
+                header.y,
+                header.width,
+                header.height,
+                header.panel()?,
+                header.packing.bits_per_pixel,
+                header.packing.order,
+                if header.packing.row_padding {
+                    ", padded rows"
+                } else {
+                    ""
+                },
+                if header.compressed { ", RLE" } else { "" },
+                header.crc32
+            );
+            match header.pixels(stored) {
+                Ok(pixels) => {
+                    println!("Header matches the pixel data");
+                    raw = pixels;
+                }
+                Err(e) if header.compressed => return Err(format!("Header check failed: {e}")),
+                Err(e) => println!("Header check failed: {e}"),
+            }
+            (
+                header.panel()?,
+                header.packing,
+                Some(u32::from(header.width)),
+            )
+        }
+        None => (*panel, *packing, width),
+    };
+    let (panel, packing) = (&panel, &packing);
+    let (width, height) = match width {
+        Some(width) if width > 0 => (1..=u32::MAX / width)
+            .take_while(|height| packing.packed_len(width, *height) <= raw.len())
+            .find(|height| packing.packed_len(width, *height) == raw.len())
+            .map(|height| (width, height))
+            .ok_or_else(|| format!("{} bytes do not split into rows of {width}", raw.len()))?,
+        Some(_) => return Err("width must be positive".to_string()),
+        None => packing
+            .dimensions(raw.len())
+            .ok_or_else(|| format!("{} bytes are not a square tile, pass --width", raw.len()))?,
+    };
+    let pixels = width * height;
+    println!(
+        "{}: {width}x{height}, {} bytes",
+        file.display(),
+        file_data.len()
+    );
+
+    let mut histogram = [0_u32; 256];
+    for value in packing.unpack(&raw, width, height)? {
+        histogram[usize::from(value)] += 1;
+    }
+    for (index, count) in histogram.iter().enumerate().filter(|(_, c)| **c > 0) {
+        let color = u8::try_from(index).ok().and_then(|v| panel.color(v));
+        let name = color
+            .and_then(indianavi_map_color::color_name)
+            .unwrap_or("invalid");
+        println!(
+            "{index:2} {name:<8} {count:7} {:5.1}%",
+            f64::from(*count) * 100.0 / f64::from(pixels)
+        );
+    }
+
+    let img = indianavi_map_color::raw_to_image(&raw, width, height, panel, packing)?;
+    if let Some(png) = png {
+        img.save(png)
+            .map_err(|e| format!("{}: {e}", png.display()))?;
+        println!("Written to {}", png.display());
+    }
+    if let Some(columns) = terminal {
+        print_terminal(&img, columns.clamp(1, width));
+    }
+    Ok(())
+}
+
+/// Renders the image with half blocks, two pixel rows per text line.
+fn print_terminal(img: &RgbImage, columns: u32) {
+    let step = f64::from(img.width()) / f64::from(columns);
+    let rows = (f64::from(img.height()) / step).floor().max(1.0) as u32;
+    let sample = |column: u32, row: u32| {
+        let x = ((f64::from(column) * step) as u32).min(img.width() - 1);
+        let y = ((f64::from(row) * step) as u32).min(img.height() - 1);
+        img.get_pixel(x, y)
+    };
+    for row in (0..rows).step_by(2) {
+        let mut line = String::new();
+        for column in 0..columns {
+            let top = sample(column, row);
+            let bottom = sample(column, row + 1);
+            line.push_str(
+                &"▀"
+                    .truecolor(top[0], top[1], top[2])
+                    .on_truecolor(bottom[0], bottom[1], bottom[2])
+                    .to_string(),
+            );
+        }
+        println!("{line}");
+    }
+}
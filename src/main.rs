@@ -1,25 +1,41 @@
+use std::collections::BTreeMap;
 use std::f64::consts::PI;
-use std::fs;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::BufWriter;
-use std::io::Read;
-use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::exit;
+use std::sync::Arc;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 
+use image::Rgb;
 use indicatif::{ProgressBar, ProgressStyle};
 
-use gpx::read;
+use indianavi_map_color::{
+    BitOrder, ColorDistance, ColorMapping, ConvertOptions, PanelProfile, PixelPacking,
+    ResampleFilter, UpscaleFilter,
+};
+
 use gpx::Gpx;
 
-use reqwest::Client;
+use indianavi_gpx_loader::{Outliers, Selection, Simplify};
+
 use tokio::task::JoinHandle;
 
-use unicode_bom::Bom;
+mod inspect;
+mod pack;
+mod poi;
+mod preview;
+mod profile;
+mod provider;
+mod source;
+mod stats;
+mod track;
+mod verify;
+
+use pack::PackWriter;
+use provider::{LargeTiles, Provider};
+use source::{TileSource, ZoomOrigin};
+use track::TrackBurner;
 
 #[derive(Parser)]
 #[command(name = "IndiaNavi Map Downloader")]
@@ -27,56 +43,351 @@ use unicode_bom::Bom;
 #[command(version = "1.0")]
 #[command(about = "Loads IndiaNavi map tiles from opentopomap.org or other specified server.", long_about = None)]
 struct Cli {
+    /// Track to load the map for: GPX, TCX, FIT, KML or GeoJSON
     #[arg(short, long)]
     gpx_path: Option<std::path::PathBuf>,
+    /// Tracks or routes of the file to use, by name or by number counting
+    /// the tracks and then the routes, e.g. 2,"Day 3"; all if not given
+    #[arg(long = "track", value_delimiter = ',', requires = "gpx_path")]
+    tracks: Vec<String>,
+    /// Segments of the chosen tracks to use, by number
+    #[arg(long = "segment", value_delimiter = ',', requires = "gpx_path")]
+    segments: Vec<usize>,
+    /// Use only track points recorded from this time on, as RFC 3339 time or
+    /// as date
+    #[arg(long, requires = "gpx_path", value_parser = indianavi_gpx_loader::parse_start)]
+    from: Option<gpx::Time>,
+    /// Use only track points recorded up to this time, as RFC 3339 time or
+    /// as date
+    #[arg(long, requires = "gpx_path", value_parser = indianavi_gpx_loader::parse_end)]
+    until: Option<gpx::Time>,
+    /// Tile server: thunderforest, thunderforest@2x or opentopomap
+    #[arg(long, default_value = "thunderforest")]
+    provider: Provider,
+    /// Tile URL with {z}, {x} and {y} placeholders, instead of the one of the
+    /// provider
     #[arg(short, long)]
     server_url: Option<String>,
+    /// Side of the source tiles in pixels, if not the one of the provider
+    #[arg(long)]
+    source_tile_size: Option<u32>,
+    /// Source tiles larger than 256px: resample to 256px at the same zoom or
+    /// split into four tiles of the next zoom
+    #[arg(long)]
+    large_tiles: Option<LargeTiles>,
+    /// Highest zoom level of the tile server, if not the one of the provider
+    #[arg(long)]
+    max_zoom: Option<u32>,
+    /// Enlarging tiles for zoom levels beyond the server: nearest or edge
+    #[arg(long, default_value_t = UpscaleFilter::Edge)]
+    overzoom: UpscaleFilter,
+    /// Filter for scaling source tiles: nearest, triangle, catmullrom or
+    /// lanczos3
+    #[arg(long, default_value_t = ResampleFilter::Triangle)]
+    resample_filter: ResampleFilter,
     #[structopt(long, number_of_values = 2)]
     point: Option<Vec<f64>>,
     #[arg(short, long, default_value_t = 10)]
     margin: u32,
+    /// Bad GPS fixes in recorded tracks, like 0,0 positions or spikes: warn
+    /// about them, drop them before computing the area, or off
+    #[arg(long, default_value_t = Outliers::Warn)]
+    outliers: Outliers,
+    /// Fastest plausible speed between two timed track points in km/h
+    #[arg(long, default_value_t = 300.0)]
+    max_speed: f64,
+    /// Longest plausible jump between two track points without time in km
+    #[arg(long, default_value_t = 20.0)]
+    max_jump: f64,
+    /// Zoom levels of the pack
+    #[arg(short, long, value_delimiter = ',', default_values_t = [14, 16])]
+    zoom: Vec<u32>,
+    /// Keep downloaded source tiles in this folder and reuse them
+    #[arg(long)]
+    source_cache: Option<PathBuf>,
+    /// Build the lower zoom levels from the source tiles of the highest one
+    /// instead of downloading them
+    #[arg(long, requires = "source_cache")]
+    downsample: bool,
     #[arg(short, long)]
     verbose: bool,
+    /// Draw the GPX track into the map tiles
+    #[arg(long, requires = "gpx_path")]
+    burn_track: bool,
+    /// Palette color of the drawn track, e.g. red or blue
+    #[arg(long, default_value = "red", value_parser = track::parse_color_name)]
+    track_color: Rgb<u8>,
+    /// Width of the drawn track per zoom level as zoom=width, e.g. 14=2,16=4
+    #[arg(long, value_delimiter = ',', value_parser = track::parse_zoom_width)]
+    track_width: Vec<(u32, u32)>,
+    /// Where the track for the device is written, next to MAPS
+    #[arg(long, default_value = "track.gpx")]
+    track_output: PathBuf,
+    /// Most track points the device can handle, longer tracks are thinned
+    #[arg(long, default_value_t = 2000)]
+    max_track_points: usize,
+    /// Simplification of the device track: off, douglas-peucker, visvalingam
+    /// or resample
+    #[arg(long, default_value_t = Simplify::DouglasPeucker)]
+    simplify: Simplify,
+    /// Tolerance of the simplification in meters, the point spacing for
+    /// resample
+    #[arg(long, default_value_t = 5.0)]
+    simplify_tolerance: f64,
+    /// Add a picture of the elevation profile to the pack, filled with the
+    /// track color
+    #[arg(long, requires = "gpx_path")]
+    profile: bool,
+    /// Size of the elevation profile picture
+    #[arg(long, default_value = "400x200", value_parser = profile::parse_size)]
+    profile_size: (u32, u32),
+    /// Palette mapping used for the tiles: generic or outdoor
+    #[arg(long, default_value_t = ColorMapping::Generic)]
+    color_map: ColorMapping,
+    /// Color difference used for palette matching: cie76, cie94 or ciede2000
+    #[arg(long, default_value_t = ColorDistance::Cie76)]
+    color_distance: ColorDistance,
+    /// Color shown through transparent parts of the source tiles, as RRGGBB
+    #[arg(long, default_value = "ffffff", value_parser = indianavi_map_color::parse_color)]
+    background: Rgb<u8>,
+    #[command(flatten)]
+    format: FormatArgs,
+    /// Start every raw tile with a header naming its format, position and
+    /// checksum
+    #[arg(long)]
+    header: bool,
+    /// Run-length encode the pixel data of every tile
+    #[arg(long, requires = "header")]
+    rle: bool,
+    /// Write colors missing from the outdoor table to this file
+    #[arg(long)]
+    unmatched_report: Option<PathBuf>,
+    /// Also write PNG previews of the converted tiles, next to the raw files
+    /// or into the given directory
+    #[arg(long, num_args = 0..=1, default_missing_value = "MAPS")]
+    preview: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-async fn download_tile(url: &str) -> Result<Vec<u8>, ()> {
-    let client = Client::builder()
-        .user_agent(
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:110.0) Gecko/20100101 Firefox/110.0",
-        )
-        .build()
-        .expect("to act like firefox");
-    let resp = client.get(url).send().await.expect("download to success");
-    let loaded_bytes = &resp.bytes().await.expect("download to have bytes");
-    let image = indianavi_map_color::convert_image(loaded_bytes)
-        .unwrap_or_else(|_b| panic!("img {:x?}", loaded_bytes));
-    Ok(image)
+/// Panel and byte layout of the raw tiles.
+#[derive(Args, Clone)]
+struct FormatArgs {
+    /// Display panel: acep7, bw, bwr, gray4 or spectra6
+    #[arg(long, default_value_t = PanelProfile::ACEP_7)]
+    panel: PanelProfile,
+    /// Bits per pixel: 1, 2, 4 or 8, defaults to the depth of the panel
+    #[arg(long)]
+    bits_per_pixel: Option<u8>,
+    /// Which end of a byte holds the first pixel: msb or lsb
+    #[arg(long, default_value_t = BitOrder::MsbFirst)]
+    bit_order: BitOrder,
+    /// Start every pixel row on a new byte
+    #[arg(long)]
+    row_padding: bool,
+}
+
+impl Cli {
+    /// The chosen provider with the overrides given on the command line.
+    fn provider(&self) -> Provider {
+        let mut provider = self.provider.clone();
+        if let Some(url) = &self.server_url {
+            provider.url.clone_from(url);
+        }
+        if let Some(tile_size) = self.source_tile_size {
+            provider.tile_size = tile_size;
+        }
+        if let Some(large_tiles) = self.large_tiles {
+            provider.large_tiles = large_tiles;
+        }
+        if let Some(max_zoom) = self.max_zoom {
+            provider.max_zoom = max_zoom;
+        }
+        provider
+    }
+}
+
+impl FormatArgs {
+    fn packing(&self) -> Result<PixelPacking, String> {
+        let mut packing = PixelPacking::for_panel(&self.panel);
+        if let Some(bits) = self.bits_per_pixel {
+            packing.bits_per_pixel = bits;
+        }
+        packing.order = self.bit_order;
+        packing.row_padding = self.row_padding;
+        packing.validate(&self.panel)?;
+        Ok(packing)
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Stitch the tiles of one zoom level into a single PNG
+    Preview {
+        #[arg(short, long)]
+        zoom: u32,
+        /// Folder holding the map pack
+        #[arg(long, default_value = "MAPS")]
+        maps: PathBuf,
+        /// Draw the track of this GPX file on top
+        #[arg(short, long)]
+        gpx_path: Option<PathBuf>,
+        #[arg(short, long, default_value = "overview.png")]
+        output: PathBuf,
+        /// Downsample the mosaic so neither side exceeds this many pixels
+        #[arg(long, default_value_t = 8192)]
+        max_size: u32,
+        #[command(flatten)]
+        format: FormatArgs,
+    },
+    /// Show size and palette usage of a raw tile and render it
+    Inspect {
+        file: PathBuf,
+        /// Tile width, needed for tiles that are not square
+        #[arg(long)]
+        width: Option<u32>,
+        /// Write the decoded tile to this PNG
+        #[arg(short, long)]
+        png: Option<PathBuf>,
+        /// Print the tile to the terminal, this many characters wide
+        #[arg(short, long, num_args = 0..=1, default_missing_value = "64")]
+        terminal: Option<u32>,
+        #[command(flatten)]
+        format: FormatArgs,
+    },
+    /// Check the raw tiles of a map pack for size, header and checksum
+    Verify {
+        /// Folder holding the map pack
+        #[arg(default_value = "MAPS")]
+        maps: PathBuf,
+        /// Fail on tiles without a header
+        #[arg(long)]
+        require_header: bool,
+        #[command(flatten)]
+        format: FormatArgs,
+    },
+    /// Print distance, ascent, descent and walking times of a track
+    Stats {
+        gpx_path: PathBuf,
+        /// Ignore elevation changes up to this many meters
+        #[arg(long, default_value_t = 5.0)]
+        smoothing: f64,
+    },
 }
 
 fn lon2tile(lon: f64, zoom: u32) -> u32 {
-    let tile = (lon + 180.0) / 360.0 * 2_u32.pow(zoom) as f64;
-    tile.floor() as u32
+    lon2x(lon, zoom).floor() as u32
 }
 
 fn lat2tile(lat: f64, zoom: u32) -> u32 {
-    let tile = (1.0 - lat.to_radians().tan().asinh() / PI) / 2.0 * 2_u32.pow(zoom) as f64;
-    tile.floor() as u32
+    lat2y(lat, zoom).floor() as u32
+}
+
+/// Fractional tile x of a longitude.
+fn lon2x(lon: f64, zoom: u32) -> f64 {
+    (lon + 180.0) / 360.0 * 2_u32.pow(zoom) as f64
+}
+
+/// Fractional tile y of a latitude.
+fn lat2y(lat: f64, zoom: u32) -> f64 {
+    (1.0 - lat.to_radians().tan().asinh() / PI) / 2.0 * 2_u32.pow(zoom) as f64
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() {
     let args = Cli::parse();
 
+    if let Some(command) = &args.command {
+        let result = match command {
+            Command::Preview {
+                zoom,
+                maps,
+                gpx_path,
+                output,
+                max_size,
+                format,
+            } => format.packing().and_then(|packing| {
+                preview::run(
+                    maps,
+                    *zoom,
+                    gpx_path.as_deref(),
+                    output,
+                    *max_size,
+                    &format.panel,
+                    &packing,
+                )
+            }),
+            Command::Inspect {
+                file,
+                width,
+                png,
+                terminal,
+                format,
+            } => format.packing().and_then(|packing| {
+                inspect::run(
+                    file,
+                    *width,
+                    png.as_deref(),
+                    *terminal,
+                    &format.panel,
+                    &packing,
+                )
+            }),
+            Command::Verify {
+                maps,
+                require_header,
+                format,
+            } => format
+                .packing()
+                .and_then(|packing| verify::run(maps, *require_header, &format.panel, &packing)),
+            Command::Stats {
+                gpx_path,
+                smoothing,
+            } => stats::run(gpx_path, *smoothing),
+        };
+        if let Err(e) = result {
+            println!("Error: {e}");
+            exit(1);
+        }
+        return;
+    }
+
     let margin = &args.margin;
 
     let mut lon_border: Option<[f64; 2]> = None;
     let mut lat_border: Option<[f64; 2]> = None;
+    let mut track: Option<Gpx> = None;
     match &args.gpx_path {
         Some(path) => {
             println!("Loading from File: {:?}", path);
-            let (lon, lat) = load_from_file(&path, &margin);
+            let selection = Selection {
+                tracks: args.tracks.clone(),
+                segments: args.segments.clone(),
+                from: args.from,
+                until: args.until,
+            };
+            let loaded = read_track(path).and_then(|mut gpx| {
+                indianavi_gpx_loader::select(&mut gpx, &selection)
+                    .map_err(|e| format!("{}: {e}", path.display()))?;
+                Ok(gpx)
+            });
+            let mut gpx = match loaded {
+                Ok(gpx) => gpx,
+                Err(e) => {
+                    println!("Error: {e}");
+                    exit(1);
+                }
+            };
+            if let Err(e) =
+                track::filter_outliers(&mut gpx, args.outliers, args.max_speed, args.max_jump)
+            {
+                println!("Error: {}: {e}", path.display());
+                exit(1);
+            }
+            let (lon, lat) = indianavi_gpx_loader::calculate_boundaries(gpx.clone(), *margin);
             lon_border = Some(lon);
             lat_border = Some(lat);
+            track = Some(gpx);
         }
         _ => {}
     }
@@ -110,58 +421,179 @@ async fn main() {
         .unwrap(),
     );
 
+    let packing = match args.format.packing() {
+        Ok(packing) => packing,
+        Err(e) => {
+            println!("Error: {e}");
+            exit(1);
+        }
+    };
+    let options = ConvertOptions {
+        mapping: args.color_map,
+        distance: args.color_distance,
+        panel: args.format.panel,
+        packing,
+        background: args.background,
+        filter: args.resample_filter,
+    };
+    let mut zooms = args.zoom.clone();
+    zooms.sort_unstable_by(|a, b| b.cmp(a));
+    zooms.dedup();
+    let mut source = TileSource::new(args.provider(), args.source_cache.clone());
+    source.downsample_from = args.downsample.then(|| zooms[0]);
+    if args.downsample {
+        let top = zooms[0].min(source.provider.max_device_zoom());
+        source.downsample_area = Some(lonlat2tiles(lon_border, margin, lat_border, top));
+    }
+    source.filter = args.resample_filter;
+    source.upscale = args.overzoom;
+    let source = Arc::new(source);
+    let origins: Vec<(u32, ZoomOrigin)> = zooms
+        .iter()
+        .rev()
+        .map(|z| (*z, source.origin(*z)))
+        .collect();
+    for (zoom, origin) in &origins {
+        if let ZoomOrigin::Synthetic(from) = origin {
+            println!("Zoom {zoom} is beyond the provider, enlarging tiles of zoom {from}");
+        }
+    }
+    let burner = match (&track, args.burn_track) {
+        (Some(gpx), true) => {
+            if options.panel.raw_value(args.track_color).is_none() {
+                println!(
+                    "Error: the {} panel cannot show the track color",
+                    options.panel
+                );
+                exit(1);
+            }
+            Some(Arc::new(TrackBurner {
+                lines: indianavi_gpx_loader::polylines(gpx),
+                color: args.track_color,
+                widths: args.track_width.iter().copied().collect(),
+            }))
+        }
+        _ => None,
+    };
+    let pack = Arc::new(PackWriter::new(
+        PathBuf::from("MAPS"),
+        options,
+        args.header,
+        args.rle,
+        args.preview.clone(),
+    ));
+
     let mut tasks: Vec<JoinHandle<Result<(), ()>>> = vec![];
-    for zoom in [14, 16] {
+    for zoom in zooms {
         let (xrange, yrange) = lonlat2tiles(lon_border, &margin, lat_border, zoom);
         pb.set_length(pb.length().unwrap_or(0) + xrange.len() as u64 * yrange.len() as u64);
+
+        // Device tiles grouped by the source tile they come from
+        let mut groups: BTreeMap<(u32, u32), Vec<(u32, u32)>> = BTreeMap::new();
         for x in xrange {
             for y in yrange.clone() {
-                let online_addr = format!("https://tile.thunderforest.com/outdoors/{zoom}/{x}/{y}.png?apikey=696e2147ac5d4d82b426dab7559a3113");
-
-                // Create a Tokio task for each path
-                let pb = pb.clone();
-                tasks.push(tokio::spawn(async move {
-                    let file_path_string = format!("MAPS/{zoom}/{x}/{y}.raw");
-                    let file_path = Path::new(&file_path_string);
-                    if file_path.exists() {
-                        pb.inc(1);
+                groups
+                    .entry(source.group(zoom, x, y))
+                    .or_default()
+                    .push((x, y));
+            }
+        }
+
+        for targets in groups.into_values() {
+            // Create a Tokio task for each path
+            let pb = pb.clone();
+            let source = source.clone();
+            let pack = pack.clone();
+            let burner = burner.clone();
+            let verbose = args.verbose;
+            tasks.push(tokio::spawn(async move {
+                let targets: Vec<(u32, u32)> = targets
+                    .into_iter()
+                    .filter(|(x, y)| {
+                        let exists = pack.exists(zoom, *x, *y);
+                        if exists {
+                            pb.inc(1);
+                        }
+                        !exists
+                    })
+                    .collect();
+                if targets.is_empty() {
+                    return Ok(());
+                }
+
+                let images = match source.images(zoom, &targets).await {
+                    Ok(images) => images,
+                    Err(e) => {
+                        pb.println(format!("Error: {e}"));
                         return Ok(());
                     }
-                    let folder_path = file_path.parent().expect("to be a path");
-                    fs::create_dir_all(&folder_path).expect("folder can be created");
-
-                    match download_tile(&online_addr).await {
-                        Ok(image) => {
-                            let mut file = BufWriter::new(
-                                fs::OpenOptions::new()
-                                    .create(true)
-                                    .write(true)
-                                    .open(file_path)
-                                    .expect("file to be opened for write"),
-                            );
-
-                            match file.write_all(&image) {
-                                Ok(()) => {
-                                    pb.inc(1);
-                                    if args.verbose {
-                                        pb.println(format!("Load: {online_addr}"));
-                                    }
-                                }
-                                Err(_) => pb.println(format!("Error: {online_addr}")),
+                };
+                for ((x, y), image) in targets.into_iter().zip(images) {
+                    let image = match &burner {
+                        Some(burner) => burner.burn(image, zoom, x, y),
+                        None => image,
+                    };
+                    let tile = indianavi_map_color::convert_tile(image, &pack.options);
+                    match pack.write(zoom, x, y, &tile) {
+                        Ok(()) => {
+                            pb.inc(1);
+                            if verbose {
+                                pb.println(format!("Load: {zoom}/{x}/{y}"));
                             }
                         }
-                        Err(_) => pb.println(format!("Error: {online_addr}")),
+                        Err(e) => pb.println(format!("Error: {zoom}/{x}/{y}: {e}")),
                     }
-                    Ok(())
-                }));
-                while tasks.len() > 100 {
-                    let _ = tasks.pop().unwrap().await;
                 }
+                Ok(())
+            }));
+            while tasks.len() > 100 {
+                let _ = tasks.pop().unwrap().await;
             }
         }
+        if args.downsample {
+            // Lower zoom levels are built from the cached tiles of this one
+            futures::future::join_all(tasks.drain(..)).await;
+        }
     }
     futures::future::join_all(tasks).await;
-    println!("done. Copy folder MAPS and file track.gpx to the root of your SD card.");
+
+    println!("{}", source.report());
+    pack.finish(args.unmatched_report.as_deref());
+    if let (Some(gpx), true) = (&track, args.profile) {
+        match profile::write(gpx, args.profile_size, args.track_color, &pack) {
+            Ok(()) => println!("Elevation profile written to MAPS/{}", pack::PROFILE_FILE),
+            Err(e) => println!("Error: elevation profile: {e}"),
+        }
+    }
+    if let Some(gpx) = track.as_ref().filter(|gpx| !gpx.waypoints.is_empty()) {
+        let zooms: Vec<u32> = origins.iter().map(|(zoom, _)| *zoom).collect();
+        match poi::write(gpx, &zooms, &pack) {
+            Ok(count) => println!("{count} waypoints written to MAPS/{}", pack::POI_FILE),
+            Err(e) => println!("Error: waypoints: {e}"),
+        }
+    }
+    if let Err(e) = pack.write_manifest(&origins) {
+        println!("Error: manifest: {e}");
+    }
+    match &track {
+        Some(gpx) => {
+            match track::write_device_track(
+                gpx,
+                &args.track_output,
+                args.simplify,
+                args.simplify_tolerance,
+                args.max_track_points,
+            ) {
+                Ok(report) => println!("{report}"),
+                Err(e) => println!("Error: {}: {e}", args.track_output.display()),
+            }
+            println!(
+                "done. Copy folder MAPS and file {} to the root of your SD card.",
+                args.track_output.display()
+            );
+        }
+        None => println!("done. Copy folder MAPS to the root of your SD card."),
+    }
 }
 
 fn lonlat2tiles(
@@ -184,23 +616,9 @@ fn lonlat2tiles(
     (xrange, yrange)
 }
 
-fn load_from_file(file_path: &PathBuf, margin: &u32) -> ([f64; 2], [f64; 2]) {
-    let mut file = File::open(file_path).unwrap();
-    let bom = Bom::from(&mut file);
-
-    let file = File::open(&file_path).unwrap();
-    let mut reader = BufReader::new(file);
-    println!("BOM: {bom}");
-    if bom != Bom::Null {
-        let mut x = [0; 3];
-        let _ = reader.read_exact(&mut x);
-        println!("strip 3 bytes from file");
-    }
-
-    // read takes any io::Read and gives a Result<Gpx, Error>.
-    let gpx: Gpx = read(reader).expect("GPX File can be read");
-
-    indianavi_gpx_loader::calculate_boundaries(gpx, *margin)
+/// Reads a track file in any format `indianavi_gpx_loader` knows.
+fn read_track(file_path: &Path) -> Result<Gpx, String> {
+    indianavi_gpx_loader::load_file(file_path).map_err(|e| format!("{}: {e}", file_path.display()))
 }
 
 fn load_from_point(point: &Vec<f64>, margin: &u32) -> ([f64; 2], [f64; 2]) {
@@ -0,0 +1,245 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use indianavi_map_color::{ConvertOptions, ConvertedTile, TileHeader, UnmatchedColors, TILE_SIZE};
+
+use crate::source::ZoomOrigin;
+
+/// Elevation profile picture in the pack, next to `pack.txt`.
+pub const PROFILE_FILE: &str = "profile.raw";
+/// Waypoints of the track, see `poi::write`.
+pub const POI_FILE: &str = "poi.bin";
+
+/// Writes converted tiles into a map pack and keeps the numbers reported
+/// once the pack is done.
+pub struct PackWriter {
+    pub maps: PathBuf,
+    pub options: ConvertOptions,
+    /// Start every tile with a `TileHeader`.
+    pub header: bool,
+    /// Run-length encode the pixel data, needs `header`.
+    pub rle: bool,
+    /// Folder for PNG previews of the tiles.
+    pub preview: Option<PathBuf>,
+    unmatched: Mutex<UnmatchedColors>,
+    unshown: Mutex<UnmatchedColors>,
+    sizes: PackSize,
+    empty_tiles: AtomicU64,
+    /// Size of the elevation profile once it is written.
+    profile: Mutex<Option<(u32, u32)>>,
+    /// Number of POIs once they are written.
+    pois: Mutex<Option<usize>>,
+}
+
+impl PackWriter {
+    pub fn new(
+        maps: PathBuf,
+        options: ConvertOptions,
+        header: bool,
+        rle: bool,
+        preview: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            maps,
+            options,
+            header,
+            rle,
+            preview,
+            unmatched: Mutex::new(UnmatchedColors::default()),
+            unshown: Mutex::new(UnmatchedColors::default()),
+            sizes: PackSize::default(),
+            empty_tiles: AtomicU64::new(0),
+            profile: Mutex::new(None),
+            pois: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self, zoom: u32, x: u32, y: u32) -> PathBuf {
+        self.maps.join(format!("{zoom}/{x}/{y}.raw"))
+    }
+
+    pub fn exists(&self, zoom: u32, x: u32, y: u32) -> bool {
+        self.path(zoom, x, y).exists()
+    }
+
+    /// Writes one tile, with its preview if asked for.
+    pub fn write(&self, zoom: u32, x: u32, y: u32, tile: &ConvertedTile) -> Result<(), String> {
+        self.unmatched.lock().unwrap().merge(&tile.unmatched);
+        self.unshown.lock().unwrap().merge(&tile.unshown);
+        if tile.empty {
+            self.empty_tiles.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(dir) = &self.preview {
+            write_preview(dir, zoom, x, y, tile).map_err(|e| format!("preview: {e}"))?;
+        }
+
+        let data = if self.rle {
+            indianavi_map_color::rle_encode(&tile.raw)
+        } else {
+            tile.raw.clone()
+        };
+        self.sizes.record(tile.raw.len(), data.len());
+        self.write_raw(&self.path(zoom, x, y), (zoom, x, y), tile, &data)
+    }
+
+    /// Writes the elevation profile picture as `PROFILE_FILE`, in the format
+    /// of the tiles.
+    pub fn write_profile(&self, picture: &ConvertedTile) -> Result<(), String> {
+        if let Some(dir) = &self.preview {
+            let png =
+                indianavi_map_color::encode_png(&picture.preview).map_err(|e| e.to_string())?;
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            fs::write(dir.join("profile.png"), png).map_err(|e| format!("preview: {e}"))?;
+        }
+        let data = if self.rle {
+            indianavi_map_color::rle_encode(&picture.raw)
+        } else {
+            picture.raw.clone()
+        };
+        self.write_raw(&self.maps.join(PROFILE_FILE), (0, 0, 0), picture, &data)?;
+        *self.profile.lock().unwrap() = Some(picture.preview.dimensions());
+        Ok(())
+    }
+
+    /// Writes the encoded POI file as `POI_FILE`.
+    pub fn write_pois(&self, data: &[u8], count: usize) -> Result<(), String> {
+        let path = self.maps.join(POI_FILE);
+        fs::create_dir_all(&self.maps).map_err(|e| e.to_string())?;
+        fs::write(&path, data).map_err(|e| format!("{}: {e}", path.display()))?;
+        *self.pois.lock().unwrap() = Some(count);
+        Ok(())
+    }
+
+    fn write_raw(
+        &self,
+        path: &Path,
+        position: (u32, u32, u32),
+        tile: &ConvertedTile,
+        data: &[u8],
+    ) -> Result<(), String> {
+        fs::create_dir_all(path.parent().expect("to be a path")).map_err(|e| e.to_string())?;
+        let mut file = fs::File::create(path).map_err(|e| e.to_string())?;
+        if self.header {
+            let mut header = TileHeader::new(
+                &self.options.panel,
+                &self.options.packing,
+                tile.preview.dimensions(),
+                position,
+                data,
+            );
+            header.compressed = self.rle;
+            file.write_all(&header.to_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        file.write_all(data).map_err(|e| e.to_string())
+    }
+
+    /// Writes `pack.txt` into the pack, one `key value` pair per line: the
+    /// tile format, then one `zoom <z> <origin>` line per zoom level where
+    /// origin is `source`, `downsampled <from>` or `synthetic <from>` for
+    /// tiles enlarged from a lower zoom level, and a
+    /// `profile <file> <width>x<height>` line if the pack has an elevation
+    /// profile and a `poi <file> <count>` line if it has waypoints.
+    pub fn write_manifest(&self, zooms: &[(u32, ZoomOrigin)]) -> Result<(), String> {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        let packing = &self.options.packing;
+        let mut manifest = format!(
+            "panel {}\ntile_size {TILE_SIZE}\nbits_per_pixel {}\nbit_order {}\nrow_padding {}\nheader {}\nrle {}\n",
+            self.options.panel,
+            packing.bits_per_pixel,
+            packing.order,
+            yes_no(packing.row_padding),
+            yes_no(self.header),
+            yes_no(self.rle),
+        );
+        for (zoom, origin) in zooms {
+            manifest.push_str(&format!("zoom {zoom} {origin}\n"));
+        }
+        if let Some((width, height)) = *self.profile.lock().unwrap() {
+            manifest.push_str(&format!("profile {PROFILE_FILE} {width}x{height}\n"));
+        }
+        if let Some(count) = *self.pois.lock().unwrap() {
+            manifest.push_str(&format!("poi {POI_FILE} {count}\n"));
+        }
+        let path = self.maps.join("pack.txt");
+        fs::create_dir_all(&self.maps).map_err(|e| e.to_string())?;
+        fs::write(&path, manifest).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// Prints what was noticed while writing the pack.
+    pub fn finish(&self, unmatched_report: Option<&Path>) {
+        if self.rle {
+            println!("{}", self.sizes.report());
+        }
+        let empty_tiles = self.empty_tiles.load(Ordering::Relaxed);
+        if empty_tiles > 0 {
+            println!("{empty_tiles} fully transparent tiles filled with the background");
+        }
+        let unmatched = self.unmatched.lock().unwrap();
+        if !unmatched.is_empty() {
+            println!("{} colors not found in the outdoor table", unmatched.len());
+        }
+        let unshown = self.unshown.lock().unwrap();
+        if !unshown.is_empty() {
+            println!(
+                "{} colors mapped to colors the {} panel cannot show, written as black",
+                unshown.len(),
+                self.options.panel
+            );
+        }
+        if let Some(path) = unmatched_report {
+            match fs::write(path, unmatched.report()) {
+                Ok(()) => println!("Unmatched colors written to {}", path.display()),
+                Err(e) => println!("Error: could not write {}: {e}", path.display()),
+            }
+        }
+    }
+}
+
+/// Pixel data and stored bytes of the tiles written to a pack.
+#[derive(Default)]
+pub struct PackSize {
+    tiles: AtomicU64,
+    raw: AtomicU64,
+    stored: AtomicU64,
+}
+
+impl PackSize {
+    pub fn record(&self, raw: usize, stored: usize) {
+        self.tiles.fetch_add(1, Ordering::Relaxed);
+        self.raw.fetch_add(raw as u64, Ordering::Relaxed);
+        self.stored.fetch_add(stored as u64, Ordering::Relaxed);
+    }
+
+    pub fn tiles(&self) -> u64 {
+        self.tiles.load(Ordering::Relaxed)
+    }
+
+    pub fn report(&self) -> String {
+        let raw = self.raw.load(Ordering::Relaxed);
+        let stored = self.stored.load(Ordering::Relaxed);
+        format!(
+            "{} tiles compressed from {} KiB to {} KiB, {:.1}% of the raw size",
+            self.tiles(),
+            raw / 1024,
+            stored / 1024,
+            stored as f64 * 100.0 / raw.max(1) as f64
+        )
+    }
+}
+
+fn write_preview(
+    dir: &Path,
+    zoom: u32,
+    x: u32,
+    y: u32,
+    tile: &ConvertedTile,
+) -> Result<(), String> {
+    let png = indianavi_map_color::encode_png(&tile.preview).map_err(|e| e.to_string())?;
+    let path = dir.join(format!("{zoom}/{x}/{y}.png"));
+    fs::create_dir_all(path.parent().expect("to be a path")).map_err(|e| e.to_string())?;
+    fs::write(&path, png).map_err(|e| e.to_string())
+}
@@ -0,0 +1,144 @@
+use gpx::Gpx;
+
+use indianavi_gpx_loader::Poi;
+use indianavi_map_color::TILE_SIZE;
+
+use crate::pack::PackWriter;
+use crate::{lat2y, lon2x};
+
+/// First bytes of the POI file.
+const MAGIC: [u8; 4] = *b"INPO";
+const VERSION: u8 = 1;
+
+/// Writes the waypoints of the track into the pack and returns how many
+/// there were.
+pub fn write(gpx: &Gpx, zooms: &[u32], pack: &PackWriter) -> Result<usize, String> {
+    let pois = indianavi_gpx_loader::pois(gpx);
+    pack.write_pois(&encode(&pois, zooms)?, pois.len())?;
+    Ok(pois.len())
+}
+
+/// Encodes the POI file for the firmware, all numbers little endian:
+///
+/// | bytes | content                                  |
+/// |-------|------------------------------------------|
+/// | 4     | magic `INPO`                             |
+/// | 1     | version, 1                               |
+/// | 1     | number of zoom levels n                  |
+/// | n     | zoom levels                              |
+/// | 2     | number of POIs                           |
+///
+/// followed by every POI:
+///
+/// | bytes | content                                          |
+/// |-------|--------------------------------------------------|
+/// | 4 + 4 | latitude and longitude in millionths of a degree |
+/// | 12 n  | per zoom level tile x, tile y as `u32` and the   |
+/// |       | pixel x, y within the tile as `u16`              |
+/// | 1 + … | name, length and UTF-8 bytes                     |
+/// | 1 + … | symbol, length and UTF-8 bytes, may be empty     |
+///
+/// Fails if the counts or zoom levels do not fit their fields.
+fn encode(pois: &[Poi], zooms: &[u32]) -> Result<Vec<u8>, String> {
+    let mut data = MAGIC.to_vec();
+    data.push(VERSION);
+    let zoom_count = u8::try_from(zooms.len())
+        .map_err(|_| format!("{} zoom levels, at most {} fit", zooms.len(), u8::MAX))?;
+    data.push(zoom_count);
+    for zoom in zooms {
+        data.push(u8::try_from(*zoom).map_err(|_| format!("zoom level {zoom} does not fit"))?);
+    }
+    let poi_count = u16::try_from(pois.len())
+        .map_err(|_| format!("{} waypoints, at most {} fit", pois.len(), u16::MAX))?;
+    data.extend_from_slice(&poi_count.to_le_bytes());
+    for poi in pois {
+        data.extend_from_slice(&((poi.lat * 1e6).round() as i32).to_le_bytes());
+        data.extend_from_slice(&((poi.lon * 1e6).round() as i32).to_le_bytes());
+        for zoom in zooms {
+            let (x, y) = (lon2x(poi.lon, *zoom), lat2y(poi.lat, *zoom));
+            data.extend_from_slice(&(x as u32).to_le_bytes());
+            data.extend_from_slice(&(y as u32).to_le_bytes());
+            data.extend_from_slice(&((x.fract() * f64::from(TILE_SIZE)) as u16).to_le_bytes());
+            data.extend_from_slice(&((y.fract() * f64::from(TILE_SIZE)) as u16).to_le_bytes());
+        }
+        push_text(&mut data, &poi.name);
+        push_text(&mut data, poi.symbol.as_deref().unwrap_or(""));
+    }
+    Ok(data)
+}
+
+/// Appends the length and up to 255 bytes of `text`, never cutting a
+/// character in half.
+fn push_text(data: &mut Vec<u8>, text: &str) {
+    let mut len = u8::try_from(text.len()).unwrap_or(u8::MAX);
+    while !text.is_char_boundary(usize::from(len)) {
+        len -= 1;
+    }
+    data.push(len);
+    data.extend_from_slice(&text.as_bytes()[..usize::from(len)]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take<const N: usize>(data: &mut &[u8]) -> [u8; N] {
+        let (head, rest) = data.split_at(N);
+        *data = rest;
+        head.try_into().unwrap()
+    }
+
+    fn take_text(data: &mut &[u8]) -> String {
+        let [len] = take::<1>(data);
+        let (text, rest) = data.split_at(usize::from(len));
+        *data = rest;
+        String::from_utf8(text.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn one_poi_at_two_zooms() {
+        let poi = Poi {
+            name: "Hütte".into(),
+            symbol: Some("Lodge".into()),
+            lon: 8.5,
+            lat: 47.25,
+        };
+        let zooms = [11, 14];
+        let written = encode(&[poi], &zooms).unwrap();
+        let mut data = written.as_slice();
+
+        assert_eq!(take::<4>(&mut data), MAGIC);
+        assert_eq!(take::<1>(&mut data), [VERSION]);
+        assert_eq!(take::<1>(&mut data), [2]);
+        assert_eq!(take::<2>(&mut data), [11, 14]);
+        assert_eq!(u16::from_le_bytes(take(&mut data)), 1);
+        assert_eq!(i32::from_le_bytes(take(&mut data)), 47_250_000);
+        assert_eq!(i32::from_le_bytes(take(&mut data)), 8_500_000);
+        // Tile x, y and pixel x, y at zoom 11 and 14
+        for expected in [(1072, 718, 91, 62), (8578, 5745, 216, 245)] {
+            let tile = (
+                u32::from_le_bytes(take(&mut data)),
+                u32::from_le_bytes(take(&mut data)),
+            );
+            let pixel = (
+                u16::from_le_bytes(take(&mut data)),
+                u16::from_le_bytes(take(&mut data)),
+            );
+            assert_eq!((tile.0, tile.1, pixel.0, pixel.1), expected);
+        }
+        assert_eq!(take_text(&mut data), "Hütte");
+        assert_eq!(take_text(&mut data), "Lodge");
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn names_are_cut_between_characters() {
+        let mut data = Vec::new();
+        push_text(&mut data, &"ä".repeat(200));
+        assert_eq!(data[0], 254);
+        assert_eq!(data.len(), 255);
+
+        let zooms = [300];
+        assert!(encode(&[], &zooms).is_err());
+    }
+}
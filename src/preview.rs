@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use image::imageops::{self, FilterType};
+use image::{Rgb, RgbImage};
+use indianavi_map_color::{PanelProfile, PixelPacking, TILE_SIZE};
+
+use crate::{lat2y, lon2x, read_track};
+
+/// Shown where the pack has no tile.
+const MISSING: Rgb<u8> = Rgb([200, 200, 200]);
+const TRACK: Rgb<u8> = Rgb([255, 0, 0]);
+
+/// Stitches all raw tiles of `zoom` in `maps` into one PNG at `output`.
+pub fn run(
+    maps: &Path,
+    zoom: u32,
+    gpx_path: Option<&Path>,
+    output: &Path,
+    max_size: u32,
+    panel: &PanelProfile,
+    packing: &PixelPacking,
+) -> Result<(), String> {
+    let tiles = list_tiles(&maps.join(zoom.to_string()))?;
+    let (Some(min_x), Some(max_x)) = (tiles.keys().next(), tiles.keys().next_back()) else {
+        return Err(format!("no tiles for zoom {zoom} in {}", maps.display()));
+    };
+    let min_y = tiles.values().flatten().min().copied().unwrap_or(0);
+    let max_y = tiles.values().flatten().max().copied().unwrap_or(0);
+    let columns = max_x - min_x + 1;
+    let rows = max_y - min_y + 1;
+
+    let tile_px = (max_size / columns.max(rows)).clamp(1, TILE_SIZE);
+    if tile_px < TILE_SIZE {
+        println!("Downsampling tiles to {tile_px}px to stay below {max_size}px");
+    }
+
+    let mut mosaic = RgbImage::from_pixel(columns * tile_px, rows * tile_px, MISSING);
+    for (x, ys) in &tiles {
+        for y in ys {
+            let path = maps.join(format!("{zoom}/{x}/{y}.raw"));
+            let raw = fs::read(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+            let mut tile = match indianavi_map_color::tile_to_image(&raw, panel, packing) {
+                Ok(tile) => tile,
+                Err(e) => {
+                    println!("Skipping {}: {e}", path.display());
+                    continue;
+                }
+            };
+            if tile_px < TILE_SIZE {
+                tile = imageops::resize(&tile, tile_px, tile_px, FilterType::Triangle);
+            }
+            imageops::replace(
+                &mut mosaic,
+                &tile,
+                i64::from((x - min_x) * tile_px),
+                i64::from((y - min_y) * tile_px),
+            );
+        }
+    }
+
+    if let Some(gpx_path) = gpx_path {
+        let gpx = read_track(gpx_path)?;
+        let scale = f64::from(tile_px);
+        for line in indianavi_gpx_loader::polylines(&gpx) {
+            let points: Vec<(f64, f64)> = line
+                .iter()
+                .map(|(lon, lat)| {
+                    (
+                        (lon2x(*lon, zoom) - f64::from(*min_x)) * scale,
+                        (lat2y(*lat, zoom) - f64::from(min_y)) * scale,
+                    )
+                })
+                .collect();
+            indianavi_map_color::draw_polyline(&mut mosaic, &points, 2, TRACK);
+        }
+    }
+
+    mosaic
+        .save(output)
+        .map_err(|e| format!("{}: {e}", output.display()))?;
+    println!(
+        "{} tiles ({columns}x{rows}) written to {}",
+        tiles.values().map(Vec::len).sum::<usize>(),
+        output.display()
+    );
+    Ok(())
+}
+
+/// Raw tile y coordinates found per x in one zoom folder.
+pub fn list_tiles(zoom_dir: &Path) -> Result<BTreeMap<u32, Vec<u32>>, String> {
+    let mut tiles = BTreeMap::new();
+    let entries = fs::read_dir(zoom_dir).map_err(|e| format!("{}: {e}", zoom_dir.display()))?;
+    for entry in entries.flatten() {
+        let Some(x) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        let Ok(files) = fs::read_dir(entry.path()) else {
+            continue;
+        };
+        let ys: Vec<u32> = files
+            .flatten()
+            .map(|f| f.path())
+            .filter(|p| p.extension().is_some_and(|e| e == "raw"))
+            .filter_map(|p| p.file_stem()?.to_str()?.parse().ok())
+            .collect();
+        if !ys.is_empty() {
+            tiles.insert(x, ys);
+        }
+    }
+    Ok(tiles)
+}
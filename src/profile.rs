@@ -0,0 +1,243 @@
+use gpx::Gpx;
+use image::{DynamicImage, Rgb, RgbImage};
+
+use indianavi_map_color::PanelProfile;
+
+use crate::pack::PackWriter;
+
+/// 3x5 pixel glyphs, one row per byte with the left pixel in bit 2.
+const FONT: [(char, [u8; 5]); 15] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('k', [0b100, 0b101, 0b110, 0b110, 0b101]),
+    ('m', [0b000, 0b110, 0b111, 0b101, 0b101]),
+    (' ', [0; 5]),
+];
+/// Glyph pixels are drawn as squares of this size.
+const SCALE: u32 = 2;
+const ADVANCE: u32 = 4 * SCALE;
+const TEXT_HEIGHT: u32 = 5 * SCALE;
+/// Least room between two distance labels.
+const TICK_SPACING: u32 = 48;
+
+/// Draws the elevation profile of the track and writes it into the pack.
+pub fn write(gpx: &Gpx, size: (u32, u32), fill: Rgb<u8>, pack: &PackWriter) -> Result<(), String> {
+    let profile = indianavi_gpx_loader::elevation_profile(gpx);
+    if profile.len() < 2 {
+        return Err("the track has no elevation data".to_string());
+    }
+    let picture = render(&profile, size, &pack.options.panel, fill);
+    let picture =
+        indianavi_map_color::convert_picture(&DynamicImage::ImageRgb8(picture), &pack.options);
+    pack.write_profile(&picture)
+}
+
+/// Draws the elevation profile, (meters from the start, elevation) pairs,
+/// with axes, distance ticks and min/max labels in the colors of `panel`.
+/// The area under the profile is filled with `fill`.
+pub fn render(
+    profile: &[(f64, f64)],
+    (width, height): (u32, u32),
+    panel: &PanelProfile,
+    fill: Rgb<u8>,
+) -> RgbImage {
+    let black = panel.nearest(Rgb([0, 0, 0]));
+    let fill = panel.nearest(fill);
+    let mut img = RgbImage::from_pixel(width, height, panel.nearest(Rgb([255, 255, 255])));
+
+    let (mut min, mut max) = profile
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(min, max), (_, e)| {
+            (min.min(*e), max.max(*e))
+        });
+    if max - min < 10.0 {
+        // Keep flat tours from filling the picture with noise
+        let middle = (min + max) / 2.0;
+        (min, max) = (middle - 5.0, middle + 5.0);
+    }
+    let total = profile.last().map_or(0.0, |p| p.0).max(1.0);
+    let (min_label, max_label) = (format!("{min:.0}m"), format!("{max:.0}m"));
+
+    let left = text_width(&min_label).max(text_width(&max_label)) + 6;
+    let right = width.saturating_sub(SCALE * 3).max(left + 1);
+    let top = TEXT_HEIGHT / 2 + 1;
+    let bottom = height.saturating_sub(TEXT_HEIGHT + 6).max(top + 1);
+    let plot_width = f64::from(right - left);
+    let x_of = |d: f64| f64::from(left) + d / total * plot_width;
+    let y_of = |e: f64| f64::from(bottom) - (e - min) / (max - min) * f64::from(bottom - top);
+
+    for x in left + 1..=right {
+        let d = f64::from(x - left) / plot_width * total;
+        let y = y_of(elevation_at(profile, d)).round().max(f64::from(top)) as u32;
+        for y in y..bottom {
+            img.put_pixel(x, y, fill);
+        }
+    }
+    let outline: Vec<(f64, f64)> = profile.iter().map(|(d, e)| (x_of(*d), y_of(*e))).collect();
+    indianavi_map_color::draw_polyline(&mut img, &outline, 1, black);
+
+    let (l, t, r, b) = (
+        f64::from(left),
+        f64::from(top),
+        f64::from(right),
+        f64::from(bottom),
+    );
+    indianavi_map_color::draw_line(&mut img, (l, t), (l, b), 1, black);
+    indianavi_map_color::draw_line(&mut img, (l, b), (r, b), 1, black);
+    for (y, label) in [(top, &max_label), (bottom, &min_label)] {
+        indianavi_map_color::draw_line(
+            &mut img,
+            (l - 3.0, f64::from(y)),
+            (l, f64::from(y)),
+            1,
+            black,
+        );
+        let x = left - 5 - text_width(label);
+        draw_text(&mut img, x, y - TEXT_HEIGHT / 2, label, black);
+    }
+
+    let total_km = total / 1000.0;
+    let step = tick_step(total_km, (right - left) / TICK_SPACING);
+    let ticks = (total_km / step).floor() as u32;
+    for i in 0..=ticks {
+        let km = f64::from(i) * step;
+        let x = x_of(km * 1000.0);
+        indianavi_map_color::draw_line(&mut img, (x, b), (x, b + 3.0), 1, black);
+        let mut label = if step < 1.0 {
+            format!("{km:.1}")
+        } else {
+            format!("{km:.0}")
+        };
+        if i == ticks {
+            label.push_str("km");
+        }
+        let label_x = (x as u32)
+            .saturating_sub(text_width(&label) / 2)
+            .min(width.saturating_sub(text_width(&label)));
+        draw_text(&mut img, label_x, bottom + 5, &label, black);
+    }
+    img
+}
+
+/// Elevation at `distance` meters, between the two nearest points.
+fn elevation_at(profile: &[(f64, f64)], distance: f64) -> f64 {
+    let next = profile.partition_point(|p| p.0 < distance);
+    let Some(&(d1, e1)) = profile.get(next) else {
+        return profile.last().map_or(0.0, |p| p.1);
+    };
+    match next.checked_sub(1).map(|i| profile[i]) {
+        Some((d0, e0)) if d1 > d0 => e0 + (e1 - e0) * (distance - d0) / (d1 - d0),
+        _ => e1,
+    }
+}
+
+/// Distance between ticks in km: 1, 2 or 5 times a power of ten, so no more
+/// than `max_ticks` fit into `total_km`.
+fn tick_step(total_km: f64, max_ticks: u32) -> f64 {
+    let max_ticks = f64::from(max_ticks.max(1));
+    let mut magnitude = 0.1;
+    loop {
+        for step in [1.0, 2.0, 5.0].map(|m| m * magnitude) {
+            if total_km / step <= max_ticks {
+                return step;
+            }
+        }
+        magnitude *= 10.0;
+    }
+}
+
+fn text_width(text: &str) -> u32 {
+    (text.chars().count() as u32 * ADVANCE).saturating_sub(SCALE)
+}
+
+fn draw_text(img: &mut RgbImage, x: u32, y: u32, text: &str, color: Rgb<u8>) {
+    for (i, c) in text.chars().enumerate() {
+        let Some((_, rows)) = FONT.iter().find(|(glyph, _)| *glyph == c) else {
+            continue;
+        };
+        let left = x + i as u32 * ADVANCE;
+        for (row, bits) in (0..).zip(rows) {
+            for column in (0..3).filter(|column| (bits >> (2 - column)) & 1 == 1) {
+                for (dx, dy) in (0..SCALE).flat_map(|dx| (0..SCALE).map(move |dy| (dx, dy))) {
+                    let (px, py) = (left + column * SCALE + dx, y + row * SCALE + dy);
+                    if px < img.width() && py < img.height() {
+                        img.put_pixel(px, py, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parses a `WIDTHxHEIGHT` size.
+pub fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("'{s}' is not WIDTHxHEIGHT"))?;
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|n| (64..=u32::from(u16::MAX)).contains(n))
+            .ok_or_else(|| format!("bad size '{s}', sides are 64 to 65535 pixels"))
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: [(f64, f64); 3] = [(0.0, 400.0), (5000.0, 900.0), (12_000.0, 600.0)];
+
+    #[test]
+    fn elevation_is_interpolated() {
+        assert!((elevation_at(&PROFILE, 2500.0) - 650.0).abs() < 1e-9);
+        assert!((elevation_at(&PROFILE, 0.0) - 400.0).abs() < 1e-9);
+        assert!((elevation_at(&PROFILE, 20_000.0) - 600.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ticks_are_round() {
+        assert!((tick_step(12.0, 5) - 5.0).abs() < 1e-9);
+        assert!((tick_step(12.0, 20) - 1.0).abs() < 1e-9);
+        assert!((tick_step(0.8, 3) - 0.5).abs() < 1e-9);
+        assert!((tick_step(130.0, 0) - 200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn profile_is_filled_and_labeled() {
+        let panel = PanelProfile::ACEP_7;
+        let fill = Rgb([0, 255, 0]);
+        let img = render(&PROFILE, (240, 120), &panel, fill);
+        assert_eq!(img.dimensions(), (240, 120));
+        assert!(img.pixels().all(|p| panel.nearest(*p) == *p));
+
+        // Filled below the highest point, white above it and left of the axis
+        let x = 5000.0 / 12_000.0 * 240.0;
+        let column = |y: u32| *img.get_pixel(x as u32 + 8, y);
+        assert_eq!(column(0), panel.nearest(Rgb([255, 255, 255])));
+        assert_eq!(column(90), panel.nearest(fill));
+        let black = panel.nearest(Rgb([0, 0, 0]));
+        assert!(img
+            .enumerate_pixels()
+            .any(|(x, y, p)| x < 20 && y < 20 && *p == black));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("400x200"), Ok((400, 200)));
+        assert!(parse_size("400").is_err());
+        assert!(parse_size("10x200").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+use std::str::FromStr;
+
+use indianavi_map_color::TILE_SIZE;
+
+/// What to do with source tiles larger than the device tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LargeTiles {
+    /// Scale every source tile down to one device tile of the same zoom.
+    Resample,
+    /// Cut a source tile of zoom z into four device tiles of zoom z + 1.
+    Split,
+}
+
+impl FromStr for LargeTiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resample" => Ok(Self::Resample),
+            "split" => Ok(Self::Split),
+            _ => Err(format!("unknown mode '{s}', use resample or split")),
+        }
+    }
+}
+
+impl fmt::Display for LargeTiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Resample => write!(f, "resample"),
+            Self::Split => write!(f, "split"),
+        }
+    }
+}
+
+/// A tile server: where tiles come from and how large they are.
+#[derive(Clone, Debug)]
+pub struct Provider {
+    pub name: String,
+    /// URL with `{z}`, `{x}` and `{y}` placeholders.
+    pub url: String,
+    /// Side of a source tile in pixels.
+    pub tile_size: u32,
+    pub large_tiles: LargeTiles,
+    /// Highest zoom level the server has tiles for.
+    pub max_zoom: u32,
+}
+
+/// Name, URL template, tile size, handling of large tiles and highest zoom.
+const KNOWN: [(&str, &str, u32, LargeTiles, u32); 3] = [
+    (
+        "thunderforest",
+        "https://tile.thunderforest.com/outdoors/{z}/{x}/{y}.png?apikey=696e2147ac5d4d82b426dab7559a3113",
+        256,
+        LargeTiles::Resample,
+        22,
+    ),
+    (
+        "thunderforest@2x",
+        "https://tile.thunderforest.com/outdoors/{z}/{x}/{y}@2x.png?apikey=696e2147ac5d4d82b426dab7559a3113",
+        512,
+        LargeTiles::Split,
+        22,
+    ),
+    (
+        "opentopomap",
+        "https://tile.opentopomap.org/{z}/{x}/{y}.png",
+        256,
+        LargeTiles::Resample,
+        17,
+    ),
+];
+
+impl Provider {
+    /// URL of one source tile.
+    pub fn url(&self, zoom: u32, x: u32, y: u32) -> String {
+        self.url
+            .replace("{z}", &zoom.to_string())
+            .replace("{x}", &x.to_string())
+            .replace("{y}", &y.to_string())
+    }
+
+    /// Whether a device tile of `zoom` is cut out of a source tile one zoom
+    /// level up.
+    pub fn splits(&self) -> bool {
+        self.tile_size > TILE_SIZE && self.large_tiles == LargeTiles::Split
+    }
+
+    /// Highest device zoom level built from tiles of the server.
+    pub fn max_device_zoom(&self) -> u32 {
+        if self.splits() {
+            self.max_zoom + 1
+        } else {
+            self.max_zoom
+        }
+    }
+}
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KNOWN
+            .iter()
+            .find(|(name, ..)| *name == s)
+            .map(|(name, url, tile_size, large_tiles, max_zoom)| Self {
+                name: (*name).to_string(),
+                url: (*url).to_string(),
+                tile_size: *tile_size,
+                large_tiles: *large_tiles,
+                max_zoom: *max_zoom,
+            })
+            .ok_or_else(|| {
+                let names: Vec<&str> = KNOWN.iter().map(|(name, ..)| *name).collect();
+                format!("unknown provider '{s}', use {}", names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
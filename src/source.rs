@@ -0,0 +1,334 @@
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use image::{DynamicImage, ImageFormat};
+use reqwest::Client;
+
+use indianavi_map_color::{ResampleFilter, UpscaleFilter};
+
+use crate::provider::Provider;
+
+/// Where the tiles of a zoom level come from, as noted in the pack manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZoomOrigin {
+    /// Tiles of the provider.
+    Source,
+    /// Scaled down from the tiles of a higher zoom level.
+    Downsampled(u32),
+    /// Enlarged from the tiles of a lower zoom level, no new detail.
+    Synthetic(u32),
+}
+
+impl fmt::Display for ZoomOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Source => write!(f, "source"),
+            Self::Downsampled(from) => write!(f, "downsampled {from}"),
+            Self::Synthetic(from) => write!(f, "synthetic {from}"),
+        }
+    }
+}
+
+type ImageFuture<'a> = Pin<Box<dyn Future<Output = Result<DynamicImage, String>> + Send + 'a>>;
+
+/// Source images for device tiles: downloaded from the provider, read from
+/// the source cache, built from the tiles of the next zoom level or enlarged
+/// from the highest zoom level the provider serves.
+pub struct TileSource {
+    pub provider: Provider,
+    /// Keeps the encoded source tiles as `{z}/{x}/{y}.tile`.
+    pub cache: Option<PathBuf>,
+    /// Build zoom levels below this one from its tiles instead of
+    /// downloading them.
+    pub downsample_from: Option<u32>,
+    /// The x and y tiles of the top zoom that are loaded. Lower zoom tiles
+    /// reaching beyond them are downloaded at their own zoom instead.
+    pub downsample_area: Option<(Range<u32>, Range<u32>)>,
+    pub filter: ResampleFilter,
+    /// Enlarges tiles beyond the highest zoom of the provider.
+    pub upscale: UpscaleFilter,
+    client: Client,
+    downloads: AtomicU64,
+    cache_hits: AtomicU64,
+    merged: AtomicU64,
+}
+
+impl TileSource {
+    pub fn new(provider: Provider, cache: Option<PathBuf>) -> Self {
+        let client = Client::builder()
+            .user_agent(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:110.0) Gecko/20100101 Firefox/110.0",
+            )
+            .build()
+            .expect("to act like firefox");
+        Self {
+            provider,
+            cache,
+            downsample_from: None,
+            downsample_area: None,
+            filter: ResampleFilter::default(),
+            upscale: UpscaleFilter::default(),
+            client,
+            downloads: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            merged: AtomicU64::new(0),
+        }
+    }
+
+    /// Highest zoom level downsampled from, never above what the provider
+    /// serves.
+    fn downsample_top(&self) -> Option<u32> {
+        self.downsample_from
+            .map(|top| top.min(self.provider.max_device_zoom()))
+    }
+
+    fn downsamples(&self, zoom: u32) -> bool {
+        self.downsample_top().is_some_and(|top| zoom < top)
+    }
+
+    /// Whether all tiles of the top zoom below a tile are in the loaded area.
+    fn in_downsample_area(&self, zoom: u32, x: u32, y: u32) -> bool {
+        let (Some(top), Some((xs, ys))) = (self.downsample_top(), &self.downsample_area) else {
+            return true;
+        };
+        let levels = top - zoom;
+        let covered = |range: &Range<u32>, v: u32| {
+            range.start <= v << levels && (v + 1) << levels <= range.end
+        };
+        covered(xs, x) && covered(ys, y)
+    }
+
+    /// Zoom levels between `zoom` and the highest one the provider serves.
+    fn overzoom_levels(&self, zoom: u32) -> Option<u32> {
+        zoom.checked_sub(self.provider.max_device_zoom())
+            .filter(|levels| *levels > 0)
+    }
+
+    fn splits(&self, zoom: u32) -> bool {
+        self.provider.splits()
+            && zoom > 0
+            && !self.downsamples(zoom)
+            && self.overzoom_levels(zoom).is_none()
+    }
+
+    pub fn origin(&self, zoom: u32) -> ZoomOrigin {
+        match (self.overzoom_levels(zoom), self.downsample_top()) {
+            (Some(_), _) => ZoomOrigin::Synthetic(self.provider.max_device_zoom()),
+            (None, Some(top)) if zoom < top => ZoomOrigin::Downsampled(top),
+            _ => ZoomOrigin::Source,
+        }
+    }
+
+    /// Device tiles of `zoom` with the same group come from one download and
+    /// are best built together.
+    pub fn group(&self, zoom: u32, x: u32, y: u32) -> (u32, u32) {
+        if let Some(levels) = self.overzoom_levels(zoom) {
+            (x >> levels, y >> levels)
+        } else if self.splits(zoom) {
+            (x / 2, y / 2)
+        } else {
+            (x, y)
+        }
+    }
+
+    /// Source images of device tiles of `zoom` that share one group.
+    pub async fn images(
+        &self,
+        zoom: u32,
+        targets: &[(u32, u32)],
+    ) -> Result<Vec<DynamicImage>, String> {
+        let mut images = Vec::with_capacity(targets.len());
+        let Some((first_x, first_y)) = targets.first() else {
+            return Ok(images);
+        };
+        if let Some(levels) = self.overzoom_levels(zoom) {
+            let (x, y) = (first_x >> levels, first_y >> levels);
+            let ancestor = self.image(zoom - levels, x, y).await?;
+            for (target_x, target_y) in targets {
+                let offset = (target_x - (x << levels), target_y - (y << levels));
+                images.push(indianavi_map_color::overzoom_tile(
+                    &ancestor,
+                    levels,
+                    offset,
+                    self.upscale,
+                ));
+            }
+        } else if self.splits(zoom) {
+            let parent = self.fetch(zoom - 1, first_x / 2, first_y / 2).await?;
+            let parts = indianavi_map_color::split_tile(&parent, self.filter);
+            for (x, y) in targets {
+                let part = (x % 2 + 2 * (y % 2)) as usize;
+                images.push(parts[part].clone());
+            }
+        } else {
+            for (x, y) in targets {
+                images.push(self.image(zoom, *x, *y).await?);
+            }
+        }
+        Ok(images)
+    }
+
+    /// Source image of one device tile.
+    fn image(&self, zoom: u32, x: u32, y: u32) -> ImageFuture<'_> {
+        Box::pin(async move {
+            if self.downsamples(zoom) {
+                // Merged tiles are cached like source tiles, so every level
+                // is built once from the one above
+                if let Some(image) = self.cached(zoom, x, y) {
+                    return Ok(image);
+                }
+                if !self.in_downsample_area(zoom, x, y) {
+                    return self.fetch(zoom, x, y).await;
+                }
+                let mut children = Vec::with_capacity(4);
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    children.push(self.image(zoom + 1, 2 * x + dx, 2 * y + dy).await?);
+                }
+                let children: [DynamicImage; 4] = children.try_into().expect("four child tiles");
+                let image = indianavi_map_color::merge_tiles(children, self.filter);
+                let merged = self.merged.fetch_add(1, Ordering::Relaxed);
+                if let Some(path) = self.cache_path(zoom, x, y) {
+                    let mut data = Vec::new();
+                    image
+                        .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                    store(&path, &data, &format!("merged{merged}"))?;
+                }
+                Ok(image)
+            } else if self.splits(zoom) {
+                let parent = self.fetch(zoom - 1, x / 2, y / 2).await?;
+                let part = (x % 2 + 2 * (y % 2)) as usize;
+                Ok(indianavi_map_color::split_tile(&parent, self.filter)
+                    .into_iter()
+                    .nth(part)
+                    .expect("four parts"))
+            } else {
+                self.fetch(zoom, x, y).await
+            }
+        })
+    }
+
+    fn cache_path(&self, zoom: u32, x: u32, y: u32) -> Option<PathBuf> {
+        self.cache
+            .as_ref()
+            .map(|dir| dir.join(format!("{zoom}/{x}/{y}.tile")))
+    }
+
+    /// A tile from the cache, if it is there and can be decoded.
+    fn cached(&self, zoom: u32, x: u32, y: u32) -> Option<DynamicImage> {
+        let data = fs::read(self.cache_path(zoom, x, y)?).ok()?;
+        let image = indianavi_map_color::decode_tile(&data).ok()?;
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        Some(image)
+    }
+
+    /// A tile of the provider, from the cache if it is there.
+    async fn fetch(&self, zoom: u32, x: u32, y: u32) -> Result<DynamicImage, String> {
+        if let Some(image) = self.cached(zoom, x, y) {
+            return Ok(image);
+        }
+
+        let url = self.provider.url(zoom, x, y);
+        let resp = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| format!("{url}: {e}"))?;
+        let data = resp.bytes().await.map_err(|e| format!("{url}: {e}"))?;
+        let download = self.downloads.fetch_add(1, Ordering::Relaxed);
+        let image = indianavi_map_color::decode_tile(&data)
+            .map_err(|e| format!("{url}: {e}, got {:x?}", &data[..data.len().min(64)]))?;
+
+        if let Some(path) = self.cache_path(zoom, x, y) {
+            store(&path, &data, &download.to_string())?;
+        }
+        Ok(image)
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{} source tiles downloaded, {} read from the cache",
+            self.downloads.load(Ordering::Relaxed),
+            self.cache_hits.load(Ordering::Relaxed)
+        );
+        let merged = self.merged.load(Ordering::Relaxed);
+        if merged > 0 {
+            report += &format!(", {merged} merged from the next zoom");
+        }
+        report
+    }
+}
+
+/// Writes a cached tile. It goes next to the target first, named after
+/// `part`, so a crash or a second task writing the same tile never leaves
+/// half a tile.
+fn store(path: &Path, data: &[u8], part: &str) -> Result<(), String> {
+    let partial = path.with_extension(format!("{part}.part"));
+    fs::create_dir_all(path.parent().expect("to be a path"))
+        .and_then(|()| fs::write(&partial, data))
+        .and_then(|()| fs::rename(&partial, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile_source(provider: &str, downsample_from: Option<u32>) -> TileSource {
+        let mut source = TileSource::new(provider.parse().unwrap(), None);
+        source.downsample_from = downsample_from;
+        source
+    }
+
+    #[test]
+    fn zoom_origins() {
+        // Up to zoom 17 from the server, enlarged beyond
+        let source = tile_source("opentopomap", None);
+        assert_eq!(source.origin(12), ZoomOrigin::Source);
+        assert_eq!(source.origin(17), ZoomOrigin::Source);
+        assert_eq!(source.origin(19), ZoomOrigin::Synthetic(17));
+
+        let source = tile_source("opentopomap", Some(14));
+        assert_eq!(source.origin(11), ZoomOrigin::Downsampled(14));
+        assert_eq!(source.origin(14), ZoomOrigin::Source);
+        // Never downsampled from a zoom the server does not have
+        let source = tile_source("opentopomap", Some(20));
+        assert_eq!(source.origin(15), ZoomOrigin::Downsampled(17));
+        assert_eq!(source.origin(18), ZoomOrigin::Synthetic(17));
+
+        // Split tiles reach one zoom further
+        let source = tile_source("thunderforest@2x", None);
+        assert_eq!(source.origin(23), ZoomOrigin::Source);
+        assert_eq!(source.origin(24), ZoomOrigin::Synthetic(23));
+    }
+
+    #[test]
+    fn tiles_of_one_download_share_a_group() {
+        let source = tile_source("opentopomap", None);
+        assert_eq!(source.group(12, 5, 7), (5, 7));
+        assert_eq!(source.group(19, 101, 203), (25, 50));
+
+        let source = tile_source("thunderforest@2x", Some(14));
+        assert_eq!(source.group(14, 101, 51), (50, 25));
+        assert_eq!(source.group(12, 5, 7), (5, 7));
+        assert_eq!(source.group(25, 101, 51), (25, 12));
+    }
+
+    #[test]
+    fn downsample_area_covers_whole_children() {
+        let mut source = tile_source("opentopomap", Some(14));
+        source.downsample_area = Some((8..16, 4..8));
+        assert!(source.in_downsample_area(13, 4, 2));
+        assert!(source.in_downsample_area(12, 2, 1));
+        assert!(!source.in_downsample_area(13, 3, 2));
+        assert!(!source.in_downsample_area(11, 1, 0));
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::Path;
+use std::time::Duration;
+
+use indianavi_gpx_loader::{SegmentStats, Stats};
+
+use crate::read_track;
+
+/// Prints distance, climb and walking times of every segment of a track file
+/// and of the whole tour.
+pub fn run(gpx_path: &Path, smoothing: f64) -> Result<(), String> {
+    let gpx = read_track(gpx_path)?;
+    let segments = indianavi_gpx_loader::segment_stats(&gpx, smoothing);
+    if segments.is_empty() {
+        return Err(format!("{} has no tracks or routes", gpx_path.display()));
+    }
+
+    let width = segments
+        .iter()
+        .map(|s| s.name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+    println!(
+        "{:width$} {:>9} {:>7} {:>7} {:>7} {:>7} {:>8} {:>6}",
+        "", "distance", "ascent", "descent", "min", "max", "naismith", "tobler"
+    );
+    let mut total = Stats::default();
+    for SegmentStats { name, stats } in &segments {
+        println!("{}", row(name, stats, width));
+        total.merge(stats);
+    }
+    if segments.len() > 1 {
+        println!("{}", row("Total", &total, width));
+    }
+    Ok(())
+}
+
+fn row(name: &str, stats: &Stats, width: usize) -> String {
+    let elevation = |e: Option<f64>| e.map_or_else(|| "-".to_string(), |e| format!("{e:.0} m"));
+    format!(
+        "{name:width$} {:>6.2} km {:>5.0} m {:>5.0} m {:>7} {:>7} {:>8} {:>6}",
+        stats.distance / 1000.0,
+        stats.ascent,
+        stats.descent,
+        elevation(stats.min_elevation),
+        elevation(stats.max_elevation),
+        hours(stats.naismith),
+        hours(stats.tobler),
+    )
+}
+
+/// `h:mm`
+fn hours(duration: Duration) -> String {
+    let minutes = (duration.as_secs() + 30) / 60;
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
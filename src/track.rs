@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use gpx::Gpx;
+use image::{DynamicImage, Rgb, Rgba};
+use indianavi_gpx_loader::{OutlierLimits, Outliers, Simplify};
+
+use crate::{lat2y, lon2x};
+
+/// Draws GPX tracks and routes onto source tiles before they are converted,
+/// for firmware that does not draw the track itself.
+pub struct TrackBurner {
+    /// (lon, lat) points of every track segment and route.
+    pub lines: Vec<Vec<(f64, f64)>>,
+    pub color: Rgb<u8>,
+    /// Line width in device pixels for single zoom levels.
+    pub widths: BTreeMap<u32, u32>,
+}
+
+impl TrackBurner {
+    /// Line width at `zoom`: the configured one or 3px at zoom 14, one pixel
+    /// more per zoom level above.
+    pub fn width(&self, zoom: u32) -> u32 {
+        self.widths
+            .get(&zoom)
+            .copied()
+            .unwrap_or_else(|| zoom.saturating_sub(11).max(1))
+    }
+
+    /// Draws the track onto the source image of device tile `zoom`/`x`/`y`,
+    /// without anti-aliasing so it stays a single palette color.
+    pub fn burn(&self, image: DynamicImage, zoom: u32, x: u32, y: u32) -> DynamicImage {
+        let mut image = image.into_rgba8();
+        // Source images may be larger than the device tile
+        let scale = f64::from(image.width());
+        let width = self.width(zoom) * image.width() / indianavi_map_color::TILE_SIZE;
+        let [r, g, b] = self.color.0;
+        for line in &self.lines {
+            let points: Vec<(f64, f64)> = line
+                .iter()
+                .map(|(lon, lat)| {
+                    (
+                        (lon2x(*lon, zoom) - f64::from(x)) * scale,
+                        (lat2y(*lat, zoom) - f64::from(y)) * scale,
+                    )
+                })
+                .collect();
+            indianavi_map_color::draw_polyline(
+                &mut image,
+                &points,
+                width.max(1),
+                Rgba([r, g, b, 255]),
+            );
+        }
+        DynamicImage::ImageRgba8(image)
+    }
+}
+
+/// Writes the track in the form the device reads, see
+/// `indianavi_gpx_loader::device_track`, and tells how many points it kept.
+pub fn write_device_track(
+    gpx: &Gpx,
+    path: &Path,
+    method: Simplify,
+    tolerance: f64,
+    max_points: usize,
+) -> Result<String, String> {
+    let device = indianavi_gpx_loader::device_track(gpx, method, tolerance, max_points);
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    gpx::write(&device, BufWriter::new(file)).map_err(|e| e.to_string())?;
+
+    let simplified = match method {
+        Simplify::Off => String::new(),
+        _ => format!(" simplified with {method} ({tolerance} m)"),
+    };
+    Ok(format!(
+        "Track{simplified} from {} to {} points, written to {}",
+        indianavi_gpx_loader::point_count(gpx),
+        indianavi_gpx_loader::point_count(&device),
+        path.display()
+    ))
+}
+
+/// Finds bad GPS fixes with a `max_speed` in km/h and a `max_jump` in km,
+/// lists them and drops them if asked to. Fails if no point is left.
+pub fn filter_outliers(
+    gpx: &mut Gpx,
+    mode: Outliers,
+    max_speed: f64,
+    max_jump: f64,
+) -> Result<(), String> {
+    let limits = OutlierLimits {
+        max_speed: max_speed / 3.6,
+        max_jump: max_jump * 1000.0,
+    };
+    let outliers = indianavi_gpx_loader::filter_outliers(gpx, &limits, mode);
+    for o in &outliers {
+        println!(
+            "Outlier: {} point {} at {:.5}, {:.5}",
+            o.line, o.number, o.lat, o.lon
+        );
+    }
+    match (outliers.len(), mode) {
+        (0, _) => {}
+        (n, Outliers::Drop) => println!("Dropped {n} outliers before computing the area"),
+        (n, _) => println!("Kept {n} outliers, use --outliers drop to remove them"),
+    }
+    if indianavi_gpx_loader::point_count(gpx) == 0 {
+        return Err("no points left after dropping outliers".to_string());
+    }
+    Ok(())
+}
+
+/// Parses a `zoom=width` pair.
+pub fn parse_zoom_width(s: &str) -> Result<(u32, u32), String> {
+    let (zoom, width) = s
+        .split_once('=')
+        .ok_or_else(|| format!("'{s}' is not zoom=width"))?;
+    let zoom = zoom
+        .trim()
+        .parse()
+        .map_err(|_| format!("bad zoom in '{s}'"))?;
+    let width = width
+        .trim()
+        .parse()
+        .map_err(|_| format!("bad width in '{s}'"))?;
+    Ok((zoom, width))
+}
+
+/// Parses a palette color name like red or blue.
+pub fn parse_color_name(s: &str) -> Result<Rgb<u8>, String> {
+    indianavi_map_color::color_by_name(s)
+        .ok_or_else(|| format!("unknown color '{s}', use e.g. red, blue or black"))
+}
@@ -0,0 +1,97 @@
+use std::fs;
+use std::path::Path;
+
+use indianavi_map_color::{PanelProfile, PixelPacking, TileHeader, TILE_SIZE};
+
+use crate::pack::PackSize;
+
+use crate::preview::list_tiles;
+
+/// Checks every raw tile below `maps`. Tiles with a header are checked
+/// against it, bare tiles against the size `panel` and `packing` give.
+pub fn run(
+    maps: &Path,
+    require_header: bool,
+    panel: &PanelProfile,
+    packing: &PixelPacking,
+) -> Result<(), String> {
+    let mut zooms: Vec<u32> = fs::read_dir(maps)
+        .map_err(|e| format!("{}: {e}", maps.display()))?
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect();
+    zooms.sort_unstable();
+
+    let (mut checked, mut with_header, mut failed) = (0, 0, 0);
+    let sizes = PackSize::default();
+    for zoom in zooms {
+        for (x, ys) in list_tiles(&maps.join(zoom.to_string()))? {
+            for y in ys {
+                let path = maps.join(format!("{zoom}/{x}/{y}.raw"));
+                checked += 1;
+                match verify_tile(&path, (zoom, x, y), require_header, panel, packing) {
+                    Ok(Some((header, stored))) => {
+                        with_header += 1;
+                        if header.compressed {
+                            let raw = header
+                                .packing
+                                .packed_len(u32::from(header.width), u32::from(header.height));
+                            sizes.record(raw, stored);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        failed += 1;
+                        println!("{}: {e}", path.display());
+                    }
+                }
+            }
+        }
+    }
+
+    println!("{checked} tiles checked, {with_header} with header, {failed} failed");
+    if sizes.tiles() > 0 {
+        println!("{}", sizes.report());
+    }
+    if failed > 0 {
+        return Err(format!("{failed} tiles failed verification"));
+    }
+    Ok(())
+}
+
+/// Returns the header of the tile, if it has one, with the size of the
+/// stored pixel data.
+fn verify_tile(
+    path: &Path,
+    (zoom, x, y): (u32, u32, u32),
+    require_header: bool,
+    panel: &PanelProfile,
+    packing: &PixelPacking,
+) -> Result<Option<(TileHeader, usize)>, String> {
+    let file = fs::read(path).map_err(|e| e.to_string())?;
+    match TileHeader::split(&file)? {
+        (Some(header), data) => {
+            header.verify(data)?;
+            let position = (u32::from(header.zoom), header.x, header.y);
+            if position != (zoom, x, y) {
+                return Err(format!(
+                    "header places the tile at {}/{}/{}",
+                    position.0, position.1, position.2
+                ));
+            }
+            Ok(Some((header, data.len())))
+        }
+        (None, _) if require_header => Err("no header".to_string()),
+        (None, data) => {
+            packing.validate(panel)?;
+            let expected = packing.packed_len(TILE_SIZE, TILE_SIZE);
+            if data.len() != expected {
+                return Err(format!(
+                    "{} bytes, a {TILE_SIZE}px {panel} tile takes {expected}",
+                    data.len()
+                ));
+            }
+            Ok(None)
+        }
+    }
+}